@@ -0,0 +1,234 @@
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+use super::{Chunk, OpCode};
+use crate::object::Function;
+use crate::value::Value;
+
+pub fn disassemble_chunk(chunk: &Chunk, name: &str) -> String {
+    let mut listing = format!("== {} ==\n", name);
+
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(chunk, offset, &mut listing);
+    }
+
+    listing
+}
+
+/// Listing for function followed by the functions nested in its constant pool
+pub fn disassemble_function(function: &Function) -> String {
+    let mut listing = disassemble_chunk(&function.chunk, &function.to_string());
+
+    for constant in function.chunk.constants.iter() {
+        if let Value::Function(nested) = constant {
+            listing.push_str(&disassemble_function(nested));
+        }
+    }
+
+    listing
+}
+
+/// Appends one instruction to listing and returns the offset of the next one
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize, listing: &mut String) -> usize {
+    write!(listing, "{:04} ", offset).unwrap();
+    if offset > 0 && chunk.line(offset) == chunk.line(offset - 1) {
+        listing.push_str("   | ");
+    } else {
+        write!(listing, "{:4} ", chunk.line(offset)).unwrap();
+    }
+
+    let op = match OpCode::try_from(chunk.code[offset]) {
+        Ok(op) => op,
+        Err(byte) => {
+            writeln!(listing, "Unknown opcode {}", byte).unwrap();
+            return offset + 1;
+        }
+    };
+
+    match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::DefineConstant
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method
+        | OpCode::ClassMethod
+        | OpCode::Getter
+        | OpCode::Trait
+        | OpCode::Import
+        | OpCode::HasField
+        | OpCode::AssertFail
+        | OpCode::DefineTest => constant_instruction(op, chunk, offset, listing),
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call
+        | OpCode::BuildList
+        | OpCode::BuildMap
+        | OpCode::MatchRange
+        | OpCode::BuildRange
+        | OpCode::GetVariantValue => byte_instruction(op, chunk, offset, listing),
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::PushHandler | OpCode::IterCheck => {
+            jump_instruction(op, 1, chunk, offset, listing)
+        }
+        OpCode::Loop => jump_instruction(op, -1, chunk, offset, listing),
+        OpCode::JumpIfPassed => byte_jump_instruction(op, "parameter", chunk, offset, listing),
+        OpCode::IterNext => byte_jump_instruction(op, "slot", chunk, offset, listing),
+        OpCode::Invoke | OpCode::SuperInvoke => invoke_instruction(op, chunk, offset, listing),
+        OpCode::CallNamed => call_named_instruction(op, chunk, offset, listing),
+        OpCode::MatchList => match_list_instruction(op, chunk, offset, listing),
+        OpCode::MatchVariant => match_variant_instruction(op, chunk, offset, listing),
+        OpCode::Closure => closure_instruction(op, chunk, offset, listing),
+        _ => simple_instruction(op, offset, listing),
+    }
+}
+
+fn simple_instruction(op: OpCode, offset: usize, listing: &mut String) -> usize {
+    writeln!(listing, "{}", op.name()).unwrap();
+    offset + 1
+}
+
+fn byte_instruction(op: OpCode, chunk: &Chunk, offset: usize, listing: &mut String) -> usize {
+    let slot = chunk.code[offset + 1];
+    writeln!(listing, "{:<16} {:4}", op.name(), slot).unwrap();
+    offset + 2
+}
+
+fn constant_instruction(op: OpCode, chunk: &Chunk, offset: usize, listing: &mut String) -> usize {
+    let constant = chunk.code[offset + 1];
+    writeln!(
+        listing,
+        "{:<16} {:4} '{}'",
+        op.name(),
+        constant,
+        chunk.constants[constant as usize]
+    )
+    .unwrap();
+    offset + 2
+}
+
+fn invoke_instruction(op: OpCode, chunk: &Chunk, offset: usize, listing: &mut String) -> usize {
+    let constant = chunk.code[offset + 1];
+    let arg_count = chunk.code[offset + 2];
+    writeln!(
+        listing,
+        "{:<16} ({} args) {:4} '{}'",
+        op.name(),
+        arg_count,
+        constant,
+        chunk.constants[constant as usize]
+    )
+    .unwrap();
+    offset + 3
+}
+
+fn call_named_instruction(op: OpCode, chunk: &Chunk, offset: usize, listing: &mut String) -> usize {
+    let positional = chunk.code[offset + 1];
+    let named = chunk.code[offset + 2];
+    writeln!(
+        listing,
+        "{:<16} {:4} + {} named",
+        op.name(),
+        positional,
+        named
+    )
+    .unwrap();
+    offset + 3
+}
+
+fn match_list_instruction(op: OpCode, chunk: &Chunk, offset: usize, listing: &mut String) -> usize {
+    let length = chunk.code[offset + 1];
+    let has_rest = chunk.code[offset + 2] != 0;
+    let rest = if has_rest { " + rest" } else { "" };
+    writeln!(listing, "{:<16} {:4}{}", op.name(), length, rest).unwrap();
+    offset + 3
+}
+
+fn match_variant_instruction(
+    op: OpCode,
+    chunk: &Chunk,
+    offset: usize,
+    listing: &mut String,
+) -> usize {
+    let length = chunk.code[offset + 1];
+    let has_values = chunk.code[offset + 2] != 0;
+    if has_values {
+        writeln!(listing, "{:<16} {:4} values", op.name(), length).unwrap();
+    } else {
+        writeln!(listing, "{:<16} any values", op.name()).unwrap();
+    }
+    offset + 3
+}
+
+fn closure_instruction(op: OpCode, chunk: &Chunk, offset: usize, listing: &mut String) -> usize {
+    let constant = chunk.code[offset + 1];
+    let function = &chunk.constants[constant as usize];
+    writeln!(listing, "{:<16} {:4} {}", op.name(), constant, function).unwrap();
+
+    // one (is_local, index) pair follows for every captured variable
+    let upvalue_count = match function {
+        Value::Function(function) => function.upvalue_count,
+        _ => 0,
+    };
+    let mut offset = offset + 2;
+    for _ in 0..upvalue_count {
+        let kind = if chunk.code[offset] == 1 {
+            "local"
+        } else {
+            "upvalue"
+        };
+        let index = chunk.code[offset + 1];
+        writeln!(
+            listing,
+            "{:04}    |                     {} {}",
+            offset, kind, index
+        )
+        .unwrap();
+        offset += 2;
+    }
+
+    offset
+}
+
+fn jump_instruction(
+    op: OpCode,
+    sign: isize,
+    chunk: &Chunk,
+    offset: usize,
+    listing: &mut String,
+) -> usize {
+    let jump = u16::from_be_bytes([chunk.code[offset + 1], chunk.code[offset + 2]]);
+    let target = offset as isize + 3 + sign * jump as isize;
+    writeln!(listing, "{:<16} {:4} -> {}", op.name(), offset, target).unwrap();
+    offset + 3
+}
+
+// a forward jump that also takes a byte operand, operand says what the byte is
+fn byte_jump_instruction(
+    op: OpCode,
+    operand: &str,
+    chunk: &Chunk,
+    offset: usize,
+    listing: &mut String,
+) -> usize {
+    let byte = chunk.code[offset + 1];
+    let jump = u16::from_be_bytes([chunk.code[offset + 2], chunk.code[offset + 3]]);
+    let target = offset + 4 + jump as usize;
+    writeln!(
+        listing,
+        "{:<16} {:4} -> {} ({} {})",
+        op.name(),
+        offset,
+        target,
+        operand,
+        byte
+    )
+    .unwrap();
+    offset + 4
+}
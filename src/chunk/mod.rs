@@ -0,0 +1,280 @@
+use std::convert::TryFrom;
+
+pub mod disassembler;
+#[cfg(test)]
+mod tests;
+
+use crate::value::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpCode {
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal,
+    SetLocal,
+    GetGlobal,
+    DefineGlobal,
+    DefineConstant,
+    SetGlobal,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    GetSuper,
+    GetIndex,
+    SetIndex,
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    JumpIfPassed,
+    Call,
+    CallNamed,
+    Invoke,
+    SuperInvoke,
+    Closure,
+    CloseUpvalue,
+    Return,
+    Class,
+    Inherit,
+    Method,
+    ClassMethod,
+    Getter,
+    Trait,
+    UseTrait,
+    Import,
+    PushHandler,
+    PopHandler,
+    Throw,
+    BuildList,
+    BuildMap,
+    MatchRange,
+    MatchInstance,
+    HasField,
+    MatchList,
+    NoMatch,
+    BuildRange,
+    GetIter,
+    IterNext,
+    IterCheck,
+    Yield,
+    MatchVariant,
+    GetVariantValue,
+    AssertFail,
+    DefineTest,
+}
+
+// every opcode in discriminant order, so a byte can be mapped back to its opcode
+const OPCODES: [OpCode; 66] = [
+    OpCode::Constant,
+    OpCode::Nil,
+    OpCode::True,
+    OpCode::False,
+    OpCode::Pop,
+    OpCode::GetLocal,
+    OpCode::SetLocal,
+    OpCode::GetGlobal,
+    OpCode::DefineGlobal,
+    OpCode::DefineConstant,
+    OpCode::SetGlobal,
+    OpCode::GetUpvalue,
+    OpCode::SetUpvalue,
+    OpCode::GetProperty,
+    OpCode::SetProperty,
+    OpCode::GetSuper,
+    OpCode::GetIndex,
+    OpCode::SetIndex,
+    OpCode::Equal,
+    OpCode::Greater,
+    OpCode::Less,
+    OpCode::Add,
+    OpCode::Subtract,
+    OpCode::Multiply,
+    OpCode::Divide,
+    OpCode::Not,
+    OpCode::Negate,
+    OpCode::Print,
+    OpCode::Jump,
+    OpCode::JumpIfFalse,
+    OpCode::Loop,
+    OpCode::JumpIfPassed,
+    OpCode::Call,
+    OpCode::CallNamed,
+    OpCode::Invoke,
+    OpCode::SuperInvoke,
+    OpCode::Closure,
+    OpCode::CloseUpvalue,
+    OpCode::Return,
+    OpCode::Class,
+    OpCode::Inherit,
+    OpCode::Method,
+    OpCode::ClassMethod,
+    OpCode::Getter,
+    OpCode::Trait,
+    OpCode::UseTrait,
+    OpCode::Import,
+    OpCode::PushHandler,
+    OpCode::PopHandler,
+    OpCode::Throw,
+    OpCode::BuildList,
+    OpCode::BuildMap,
+    OpCode::MatchRange,
+    OpCode::MatchInstance,
+    OpCode::HasField,
+    OpCode::MatchList,
+    OpCode::NoMatch,
+    OpCode::BuildRange,
+    OpCode::GetIter,
+    OpCode::IterNext,
+    OpCode::IterCheck,
+    OpCode::Yield,
+    OpCode::MatchVariant,
+    OpCode::GetVariantValue,
+    OpCode::AssertFail,
+    OpCode::DefineTest,
+];
+
+impl OpCode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::Constant => "OP_CONSTANT",
+            OpCode::Nil => "OP_NIL",
+            OpCode::True => "OP_TRUE",
+            OpCode::False => "OP_FALSE",
+            OpCode::Pop => "OP_POP",
+            OpCode::GetLocal => "OP_GET_LOCAL",
+            OpCode::SetLocal => "OP_SET_LOCAL",
+            OpCode::GetGlobal => "OP_GET_GLOBAL",
+            OpCode::DefineGlobal => "OP_DEFINE_GLOBAL",
+            OpCode::DefineConstant => "OP_DEFINE_CONSTANT",
+            OpCode::SetGlobal => "OP_SET_GLOBAL",
+            OpCode::GetUpvalue => "OP_GET_UPVALUE",
+            OpCode::SetUpvalue => "OP_SET_UPVALUE",
+            OpCode::GetProperty => "OP_GET_PROPERTY",
+            OpCode::SetProperty => "OP_SET_PROPERTY",
+            OpCode::GetSuper => "OP_GET_SUPER",
+            OpCode::GetIndex => "OP_GET_INDEX",
+            OpCode::SetIndex => "OP_SET_INDEX",
+            OpCode::Equal => "OP_EQUAL",
+            OpCode::Greater => "OP_GREATER",
+            OpCode::Less => "OP_LESS",
+            OpCode::Add => "OP_ADD",
+            OpCode::Subtract => "OP_SUBTRACT",
+            OpCode::Multiply => "OP_MULTIPLY",
+            OpCode::Divide => "OP_DIVIDE",
+            OpCode::Not => "OP_NOT",
+            OpCode::Negate => "OP_NEGATE",
+            OpCode::Print => "OP_PRINT",
+            OpCode::Jump => "OP_JUMP",
+            OpCode::JumpIfFalse => "OP_JUMP_IF_FALSE",
+            OpCode::Loop => "OP_LOOP",
+            OpCode::JumpIfPassed => "OP_JUMP_IF_PASSED",
+            OpCode::Call => "OP_CALL",
+            OpCode::CallNamed => "OP_CALL_NAMED",
+            OpCode::Invoke => "OP_INVOKE",
+            OpCode::SuperInvoke => "OP_SUPER_INVOKE",
+            OpCode::Closure => "OP_CLOSURE",
+            OpCode::CloseUpvalue => "OP_CLOSE_UPVALUE",
+            OpCode::Return => "OP_RETURN",
+            OpCode::Class => "OP_CLASS",
+            OpCode::Inherit => "OP_INHERIT",
+            OpCode::Method => "OP_METHOD",
+            OpCode::ClassMethod => "OP_CLASS_METHOD",
+            OpCode::Getter => "OP_GETTER",
+            OpCode::Trait => "OP_TRAIT",
+            OpCode::UseTrait => "OP_USE_TRAIT",
+            OpCode::Import => "OP_IMPORT",
+            OpCode::PushHandler => "OP_PUSH_HANDLER",
+            OpCode::PopHandler => "OP_POP_HANDLER",
+            OpCode::Throw => "OP_THROW",
+            OpCode::BuildList => "OP_BUILD_LIST",
+            OpCode::BuildMap => "OP_BUILD_MAP",
+            OpCode::MatchRange => "OP_MATCH_RANGE",
+            OpCode::MatchInstance => "OP_MATCH_INSTANCE",
+            OpCode::HasField => "OP_HAS_FIELD",
+            OpCode::MatchList => "OP_MATCH_LIST",
+            OpCode::NoMatch => "OP_NO_MATCH",
+            OpCode::BuildRange => "OP_BUILD_RANGE",
+            OpCode::GetIter => "OP_GET_ITER",
+            OpCode::IterNext => "OP_ITER_NEXT",
+            OpCode::IterCheck => "OP_ITER_CHECK",
+            OpCode::Yield => "OP_YIELD",
+            OpCode::MatchVariant => "OP_MATCH_VARIANT",
+            OpCode::GetVariantValue => "OP_GET_VARIANT_VALUE",
+            OpCode::AssertFail => "OP_ASSERT_FAIL",
+            OpCode::DefineTest => "OP_DEFINE_TEST",
+        }
+    }
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        OPCODES.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+// consecutive instructions on the same source line share one entry
+#[derive(Debug, Clone, PartialEq)]
+struct LineRun {
+    line: usize,
+    count: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    lines: Vec<LineRun>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn write(&mut self, byte: u8, line: usize) {
+        self.code.push(byte);
+
+        match self.lines.last_mut() {
+            Some(run) if run.line == line => run.count += 1,
+            _ => self.lines.push(LineRun { line, count: 1 }),
+        }
+    }
+
+    pub fn write_op(&mut self, op: OpCode, line: usize) {
+        self.write(op as u8, line);
+    }
+
+    /// Returns the index of the value in the constant pool
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// Source line of the byte at offset
+    pub fn line(&self, offset: usize) -> usize {
+        let mut remaining = offset;
+        for run in self.lines.iter() {
+            if remaining < run.count {
+                return run.line;
+            }
+            remaining -= run.count;
+        }
+
+        panic!("no line recorded for offset {}", offset);
+    }
+}
@@ -0,0 +1,96 @@
+use super::disassembler::*;
+use super::*;
+
+#[test]
+fn opcode_round_trips_through_byte() {
+    for op in OPCODES.iter() {
+        assert_eq!(OpCode::try_from(*op as u8), Ok(*op));
+    }
+
+    assert_eq!(
+        OpCode::try_from(OPCODES.len() as u8),
+        Err(OPCODES.len() as u8)
+    );
+}
+
+#[test]
+fn line_table_is_run_length_encoded() {
+    let mut chunk = Chunk::new();
+    chunk.write_op(OpCode::Nil, 1);
+    chunk.write_op(OpCode::Pop, 1);
+    chunk.write_op(OpCode::True, 1);
+    chunk.write_op(OpCode::Print, 3);
+    chunk.write_op(OpCode::Return, 4);
+
+    assert_eq!(chunk.lines.len(), 3);
+    let lines: Vec<usize> = (0..chunk.code.len()).map(|i| chunk.line(i)).collect();
+    assert_eq!(lines, vec![1, 1, 1, 3, 4]);
+}
+
+#[test]
+fn disassemble_constant_and_return() {
+    let mut chunk = Chunk::new();
+    let constant = chunk.add_constant(Value::Number(1.2));
+    chunk.write_op(OpCode::Constant, 123);
+    chunk.write(constant as u8, 123);
+    chunk.write_op(OpCode::Negate, 123);
+    chunk.write_op(OpCode::Return, 124);
+
+    assert_eq!(
+        disassemble_chunk(&chunk, "test chunk"),
+        "== test chunk ==\n\
+         0000  123 OP_CONSTANT         0 '1.2'\n\
+         0002    | OP_NEGATE\n\
+         0003  124 OP_RETURN\n"
+    );
+}
+
+#[test]
+fn disassemble_operands() {
+    let mut chunk = Chunk::new();
+    let name = chunk.add_constant(Value::LoxString("a".into()));
+    chunk.write_op(OpCode::GetGlobal, 1);
+    chunk.write(name as u8, 1);
+    chunk.write_op(OpCode::JumpIfFalse, 1);
+    chunk.write(0, 1);
+    chunk.write(2, 1);
+    chunk.write_op(OpCode::GetLocal, 2);
+    chunk.write(1, 2);
+    chunk.write_op(OpCode::Loop, 2);
+    chunk.write(0, 2);
+    chunk.write(10, 2);
+
+    assert_eq!(
+        disassemble_chunk(&chunk, "operands"),
+        "== operands ==\n\
+         0000    1 OP_GET_GLOBAL       0 'a'\n\
+         0002    | OP_JUMP_IF_FALSE    2 -> 7\n\
+         0005    2 OP_GET_LOCAL        1\n\
+         0007    | OP_LOOP             7 -> 0\n"
+    );
+}
+
+#[test]
+fn disassemble_unknown_opcode() {
+    let mut chunk = Chunk::new();
+    chunk.write(255, 1);
+
+    assert_eq!(
+        disassemble_chunk(&chunk, "bad"),
+        "== bad ==\n0000    1 Unknown opcode 255\n"
+    );
+}
+
+#[test]
+fn disassemble_invoke() {
+    let mut chunk = Chunk::new();
+    let name = chunk.add_constant(Value::LoxString("area".into()));
+    chunk.write_op(OpCode::Invoke, 7);
+    chunk.write(name as u8, 7);
+    chunk.write(2, 7);
+
+    assert_eq!(
+        disassemble_chunk(&chunk, "invoke"),
+        "== invoke ==\n0000    7 OP_INVOKE        (2 args)    0 'area'\n"
+    );
+}
@@ -0,0 +1,61 @@
+use std::{error::Error, fmt};
+
+use crate::scanner::error::ScanError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub line: usize,
+    /// " at 'token'" or " at end"
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Error{}: {}",
+            self.line, self.location, self.message
+        )
+    }
+}
+
+/// Code that compiles but is probably a mistake, compiling goes on after reporting it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub line: usize,
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}] Warning{}: {}",
+            self.line, self.location, self.message
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompileError {
+    /// Scanner rejected the source, already reported by the scanner
+    Scan(ScanError),
+    /// Every syntax error found, in source order and already reported
+    Syntax(Vec<SyntaxError>),
+}
+
+impl Error for CompileError {}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Scan(error) => write!(f, "{}", error),
+            CompileError::Syntax(errors) => {
+                let lines: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+                write!(f, "{}", lines.join("\n"))
+            }
+        }
+    }
+}
@@ -0,0 +1,1899 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use log::warn;
+
+pub mod error;
+mod patterns;
+#[cfg(test)]
+mod tests;
+
+use crate::chunk::{Chunk, OpCode};
+use crate::object::{Enum, EnumVariant, Function};
+use crate::scanner::Scanner;
+use crate::token::*;
+use crate::value::Value;
+use error::{CompileError, SyntaxError, Warning};
+
+// local slots and upvalues are addressed by a single byte operand
+const LOCALS_MAX: usize = 256;
+const UPVALUES_MAX: usize = 256;
+
+// name anonymous functions print and show up in stack traces with
+const ANONYMOUS_NAME: &str = "anonymous";
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Range,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Self {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Range,
+            Precedence::Range => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+type ParseFn = fn(&mut Compiler, bool);
+
+#[derive(Clone, Copy)]
+struct ParseRule {
+    prefix: Option<ParseFn>,
+    infix: Option<ParseFn>,
+    precedence: Precedence,
+}
+
+fn get_rule(token_type: &TokenType) -> ParseRule {
+    let (prefix, infix, precedence): (Option<ParseFn>, Option<ParseFn>, Precedence) =
+        match token_type {
+            TokenType::LeftParen => (
+                Some(Compiler::grouping),
+                Some(Compiler::call),
+                Precedence::Call,
+            ),
+            TokenType::LeftBracket => (
+                Some(Compiler::list),
+                Some(Compiler::index),
+                Precedence::Call,
+            ),
+            TokenType::LeftBrace => (Some(Compiler::map), None, Precedence::None),
+            TokenType::Dot => (None, Some(Compiler::dot), Precedence::Call),
+            TokenType::Minus => (
+                Some(Compiler::unary),
+                Some(Compiler::binary),
+                Precedence::Term,
+            ),
+            TokenType::Plus => (None, Some(Compiler::binary), Precedence::Term),
+            TokenType::Slash | TokenType::Star => {
+                (None, Some(Compiler::binary), Precedence::Factor)
+            }
+            TokenType::Bang => (Some(Compiler::unary), None, Precedence::None),
+            TokenType::BangEqual | TokenType::EqualEqual => {
+                (None, Some(Compiler::binary), Precedence::Equality)
+            }
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => (None, Some(Compiler::binary), Precedence::Comparison),
+            TokenType::DotDot | TokenType::DotDotEqual => {
+                (None, Some(Compiler::binary), Precedence::Range)
+            }
+            TokenType::Literal(LiteralType::Identifier(_)) => {
+                (Some(Compiler::variable), None, Precedence::None)
+            }
+            TokenType::Literal(LiteralType::LoxString(_)) => {
+                (Some(Compiler::string), None, Precedence::None)
+            }
+            TokenType::Literal(LiteralType::Integer(_))
+            | TokenType::Literal(LiteralType::Float(_)) => {
+                (Some(Compiler::number), None, Precedence::None)
+            }
+            TokenType::And => (None, Some(Compiler::and), Precedence::And),
+            TokenType::Or => (None, Some(Compiler::or), Precedence::Or),
+            TokenType::False | TokenType::Nil | TokenType::True => {
+                (Some(Compiler::literal), None, Precedence::None)
+            }
+            TokenType::Fun => (Some(Compiler::lambda), None, Precedence::None),
+            TokenType::Match => (Some(Compiler::match_expression), None, Precedence::None),
+            TokenType::Super => (Some(Compiler::super_), None, Precedence::None),
+            TokenType::This => (Some(Compiler::this), None, Precedence::None),
+            _ => (None, None, Precedence::None),
+        };
+
+    ParseRule {
+        prefix,
+        infix,
+        precedence,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionType {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
+struct Local {
+    name: Rc<str>,
+    // None until the initializer has been compiled
+    depth: Option<usize>,
+    // a closure refers to it, so it has to be moved off the stack when it goes out of scope
+    is_captured: bool,
+    // the token naming it when it was declared with const
+    constant: Option<usize>,
+}
+
+// A variable from an enclosing function, either one of its locals or one of its own upvalues
+#[derive(Clone, Copy, PartialEq)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+// Per function state, nested functions push a new one while they compile
+struct FunctionState {
+    function: Function,
+    function_type: FunctionType,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    tries: Vec<TryState>,
+}
+
+impl FunctionState {
+    fn new(function: Function, function_type: FunctionType) -> Self {
+        // slot zero holds the called function, or the receiver inside methods
+        let slot_zero = match function_type {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            FunctionType::Function | FunctionType::Script => "",
+        };
+
+        Self {
+            function,
+            function_type,
+            locals: vec![Local {
+                name: Rc::from(slot_zero),
+                depth: Some(0),
+                is_captured: false,
+                constant: None,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            tries: Vec::new(),
+        }
+    }
+}
+
+// A try statement being compiled, a return inside it has to run its finally block first
+struct TryState {
+    // handlers the statement has pushed at the code being compiled, returns pop them
+    handlers: usize,
+    // first of two hidden locals, the value the try completes with and how it completed
+    finally_slot: Option<u8>,
+    // locals below this are still in scope at the finally block
+    local_count: usize,
+    // jumps from returns to the finally block
+    returns: Vec<usize>,
+}
+
+// how a try statement with a finally block completed, stored in its second hidden local
+const COMPLETED_BY_THROW: f64 = 1.0;
+const COMPLETED_BY_RETURN: f64 = 2.0;
+
+struct ClassState {
+    has_superclass: bool,
+}
+
+/// Compiles source into the top level script function
+pub fn compile(source: String) -> Result<Function, CompileError> {
+    compile_with_warnings(source).map(|(script, _)| script)
+}
+
+/// Compiles source and also hands back the warnings, which are reported either way
+pub fn compile_with_warnings(source: String) -> Result<(Function, Vec<Warning>), CompileError> {
+    let mut scanner = Scanner::new(source.clone());
+    let tokens = scanner.scan_tokens().map_err(CompileError::Scan)?;
+
+    let mut compiler = Compiler::new(tokens, source);
+    while !compiler.match_token(&TokenType::EOF) {
+        compiler.declaration();
+    }
+
+    let script = compiler.end_function();
+    if compiler.errors.is_empty() {
+        Ok((script, compiler.warnings))
+    } else {
+        Err(CompileError::Syntax(compiler.errors))
+    }
+}
+
+// Single pass compiler, parses tokens with a Pratt parser and emits bytecode as it goes
+struct Compiler {
+    tokens: Vec<Token>,
+    /// What the tokens were scanned from, assertions quote it
+    source: String,
+    current: usize,
+    previous: usize,
+    states: Vec<FunctionState>,
+    classes: Vec<ClassState>,
+    /// Globals declared with const so far and the token naming each one
+    constants: HashMap<Rc<str>, usize>,
+    /// Variant names of the enums declared so far, to find matches that miss some
+    enums: HashMap<Rc<str>, Vec<Rc<str>>>,
+    /// Method names of the traits declared so far, to find conflicts between them
+    traits: HashMap<Rc<str>, Vec<Rc<str>>>,
+    errors: Vec<SyntaxError>,
+    warnings: Vec<Warning>,
+    panic_mode: bool,
+}
+
+impl Compiler {
+    fn new(tokens: Vec<Token>, source: String) -> Self {
+        Self {
+            tokens,
+            source,
+            current: 0,
+            previous: 0,
+            states: vec![FunctionState::new(
+                Function::new(None),
+                FunctionType::Script,
+            )],
+            classes: Vec::new(),
+            constants: HashMap::new(),
+            enums: HashMap::new(),
+            traits: HashMap::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+            panic_mode: false,
+        }
+    }
+
+    fn declaration(&mut self) {
+        if self.match_token(&TokenType::Class) {
+            self.class_declaration();
+        } else if self.match_token(&TokenType::Trait) {
+            self.trait_declaration();
+        } else if self.match_token(&TokenType::Enum) {
+            self.enum_declaration();
+        } else if self.check(&TokenType::Fun)
+            && self.token_type_at(self.current + 1) != &TokenType::LeftParen
+        {
+            // `fun (` starts an anonymous function, which is an expression statement
+            self.advance();
+            self.fun_declaration();
+        } else if self.match_token(&TokenType::Var) {
+            self.var_declaration();
+        } else if self.match_token(&TokenType::Const) {
+            self.const_declaration();
+        } else if self.match_token(&TokenType::Import) {
+            self.import_declaration();
+        } else if self.is_selective_import() {
+            self.selective_import_declaration();
+        } else if self.is_test_block() {
+            self.test_declaration();
+        } else {
+            self.statement();
+        }
+
+        if self.panic_mode {
+            self.synchronize();
+        }
+    }
+
+    fn class_declaration(&mut self) {
+        let class_name = self.consume_identifier("Expect class name.");
+        let name_constant = self.identifier_constant(class_name.clone());
+        self.declare_variable(class_name.clone());
+
+        self.emit_op_byte(OpCode::Class, name_constant);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassState {
+            has_superclass: false,
+        });
+
+        if self.match_token(&TokenType::Less) {
+            let superclass_name = self.consume_identifier("Expect superclass name.");
+            self.variable(false);
+
+            if superclass_name == class_name {
+                self.error("A class can't inherit from itself.");
+            }
+
+            // the superclass stays on the stack as a local named super while methods compile
+            self.begin_scope();
+            self.add_local(Rc::from("super"));
+            self.define_variable(0);
+
+            self.named_variable(class_name.clone(), false);
+            self.emit_op(OpCode::Inherit);
+            self.classes.last_mut().unwrap().has_superclass = true;
+        }
+
+        self.named_variable(class_name, false);
+        let mut traits = Vec::new();
+        if self.match_contextual_keyword("with") {
+            loop {
+                let trait_name = self.consume_identifier("Expect trait name.");
+                traits.push((self.previous, trait_name.clone()));
+                self.named_variable(trait_name, false);
+                self.emit_op(OpCode::UseTrait);
+
+                if !self.match_token(&TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(&TokenType::LeftBrace, "Expect '{' before class body.");
+        let mut methods = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.check(&TokenType::EOF) {
+            methods.push(self.method());
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after class body.");
+        self.emit_op(OpCode::Pop);
+        self.check_trait_conflicts(&traits, &methods);
+
+        if self.classes.last().unwrap().has_superclass {
+            self.end_scope();
+        }
+        self.classes.pop();
+    }
+
+    fn trait_declaration(&mut self) {
+        let trait_name = self.consume_identifier("Expect trait name.");
+        let name_constant = self.identifier_constant(trait_name.clone());
+        self.declare_variable(trait_name.clone());
+
+        self.emit_op_byte(OpCode::Trait, name_constant);
+        self.define_variable(name_constant);
+
+        // trait methods are compiled like class methods, this is whichever class uses them
+        self.classes.push(ClassState {
+            has_superclass: false,
+        });
+        self.named_variable(trait_name.clone(), false);
+        self.consume(&TokenType::LeftBrace, "Expect '{' before trait body.");
+        let mut methods = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.check(&TokenType::EOF) {
+            methods.push(self.method());
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after trait body.");
+        self.emit_op(OpCode::Pop);
+        self.classes.pop();
+
+        self.traits.insert(trait_name, methods);
+    }
+
+    // the whole enum is known here, so it is a constant rather than built at runtime
+    fn enum_declaration(&mut self) {
+        let enum_name = self.consume_identifier("Expect enum name.");
+        let name_constant = self.identifier_constant(enum_name.clone());
+        self.declare_variable(enum_name.clone());
+
+        self.consume(&TokenType::LeftBrace, "Expect '{' before enum variants.");
+        let mut variants: Vec<Rc<EnumVariant>> = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.check(&TokenType::EOF) {
+            let name = self.consume_identifier("Expect variant name.");
+            if variants.iter().any(|variant| variant.name == name) {
+                self.error("Already a variant with this name in this enum.");
+            }
+
+            let mut fields = Vec::new();
+            if self.match_token(&TokenType::LeftParen) {
+                loop {
+                    fields.push(self.consume_identifier("Expect field name."));
+                    if !self.match_token(&TokenType::Comma) {
+                        break;
+                    }
+                }
+                self.consume(&TokenType::RightParen, "Expect ')' after variant fields.");
+            }
+            variants.push(Rc::new(EnumVariant {
+                enum_name: enum_name.clone(),
+                name,
+                fields,
+            }));
+
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after enum variants.");
+
+        let names = variants
+            .iter()
+            .map(|variant| variant.name.clone())
+            .collect();
+        self.enums.insert(enum_name.clone(), names);
+        self.emit_constant(Value::Enum(Rc::new(Enum {
+            name: enum_name,
+            variants,
+        })));
+        self.define_variable(name_constant);
+    }
+
+    // two traits defining the same method is an error unless the class overrides it, traits
+    // from other modules aren't known here
+    fn check_trait_conflicts(&mut self, traits: &[(usize, Rc<str>)], overrides: &[Rc<str>]) {
+        for (i, (_, first)) in traits.iter().enumerate() {
+            for (index, second) in traits[i + 1..].iter() {
+                let conflict = match (self.traits.get(first), self.traits.get(second)) {
+                    (Some(first_methods), Some(second_methods)) => first_methods
+                        .iter()
+                        .find(|name| second_methods.contains(name) && !overrides.contains(name))
+                        .cloned(),
+                    _ => None,
+                };
+
+                if let Some(method) = conflict {
+                    let message = format!(
+                        "Traits '{}' and '{}' both define '{}', the class must override it.",
+                        first, second, method
+                    );
+                    self.error_at(*index, &message);
+                }
+            }
+        }
+    }
+
+    // returns the method name
+    fn method(&mut self) -> Rc<str> {
+        let is_class_method = self.match_token(&TokenType::Class);
+        let name = self.consume_identifier("Expect method name.");
+        let constant = self.identifier_constant(name.clone());
+
+        if is_class_method {
+            self.function(FunctionType::Method);
+            self.emit_op_byte(OpCode::ClassMethod, constant);
+        } else if self.check(&TokenType::LeftBrace) {
+            if &*name == "init" {
+                self.error("An initializer must have a parameter list.");
+            }
+            self.getter();
+            self.emit_op_byte(OpCode::Getter, constant);
+        } else {
+            let function_type = if &*name == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.function(function_type);
+            self.emit_op_byte(OpCode::Method, constant);
+        }
+        name
+    }
+
+    // a method with no parameter list, run when the property is read
+    fn getter(&mut self) {
+        let name = self.previous_name();
+        self.begin_function(name, FunctionType::Method);
+        self.consume(&TokenType::LeftBrace, "Expect '{' before getter body.");
+        self.block();
+        self.end_closure();
+    }
+
+    fn fun_declaration(&mut self) {
+        let global = self.parse_variable("Expect function name.");
+        // a function may refer to itself, so it is usable before its body compiles
+        self.mark_initialized();
+        self.function(FunctionType::Function);
+        self.define_variable(global);
+    }
+
+    fn function(&mut self, function_type: FunctionType) {
+        let name = self.previous_name();
+        self.begin_function(name, function_type);
+        self.consume(&TokenType::LeftParen, "Expect '(' after function name.");
+        self.parameters();
+        self.consume(&TokenType::LeftBrace, "Expect '{' before function body.");
+        self.block();
+        self.end_closure();
+    }
+
+    fn begin_function(&mut self, name: Rc<str>, function_type: FunctionType) {
+        self.states
+            .push(FunctionState::new(Function::new(Some(name)), function_type));
+        self.begin_scope();
+    }
+
+    // parses the parameter list up to and including its ')'
+    fn parameters(&mut self) {
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if self.match_token(&TokenType::Ellipsis) {
+                    let constant = self.parse_variable("Expect parameter name after '...'.");
+                    self.define_variable(constant);
+                    self.state_mut().function.has_rest = true;
+                    if self.check(&TokenType::Comma) {
+                        self.error_at_current("A rest parameter must be the last parameter.");
+                    }
+                    break;
+                }
+
+                self.state_mut().function.arity += 1;
+                if self.state().function.arity > 255 {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                }
+
+                let constant = self.parse_variable("Expect parameter name.");
+                let name = self.previous_name();
+                self.state_mut().function.parameters.push(name);
+                if self.match_token(&TokenType::Equal) {
+                    self.default_parameter();
+                } else if self.state().function.optional > 0 {
+                    self.error("A parameter without a default can't follow one with a default.");
+                }
+                self.define_variable(constant);
+
+                if !self.match_token(&TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(&TokenType::RightParen, "Expect ')' after parameters.");
+    }
+
+    // the parameter is declared but not yet initialized, so the default can only see earlier ones
+    fn default_parameter(&mut self) {
+        let parameter = self.state().function.arity - 1;
+        self.state_mut().function.optional += 1;
+
+        self.emit_op_byte(OpCode::JumpIfPassed, parameter as u8);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        let skip = self.chunk().code.len() - 2;
+
+        self.expression();
+        let slot = self.state().locals.len() - 1;
+        self.emit_op_byte(OpCode::SetLocal, slot as u8);
+        self.emit_op(OpCode::Pop);
+        self.patch_jump(skip);
+    }
+
+    /// Finishes the function being compiled and emits the closure for it in the enclosing one
+    fn end_closure(&mut self) {
+        // no end_scope, the whole frame is discarded when the function returns
+        let upvalues = self.state().upvalues.clone();
+        let function = self.end_function();
+        let constant = self.make_constant(Value::Function(Rc::new(function)));
+        self.emit_op_byte(OpCode::Closure, constant);
+
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+    }
+
+    // import "path" as name;
+    fn import_declaration(&mut self) {
+        let path = self.module_path("Expect module path after 'import'.");
+        if !self.match_contextual_keyword("as") {
+            self.error_at_current("Expect 'as' after module path.");
+        }
+        let global = self.parse_variable("Expect module name after 'as'.");
+        self.emit_op_byte(OpCode::Import, path);
+        self.define_variable(global);
+        self.consume(&TokenType::Semicolon, "Expect ';' after import.");
+    }
+
+    // from "path" import a, b;
+    fn selective_import_declaration(&mut self) {
+        self.advance();
+        let path = self.module_path("Expect module path after 'from'.");
+        self.consume(&TokenType::Import, "Expect 'import' after module path.");
+        loop {
+            let global = self.parse_variable("Expect imported name.");
+            let name_constant = self.identifier_constant(self.previous_name());
+            // modules run once, later imports of the same path just look it up
+            self.emit_op_byte(OpCode::Import, path);
+            self.emit_op_byte(OpCode::GetProperty, name_constant);
+            self.define_variable(global);
+
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(&TokenType::Semicolon, "Expect ';' after import.");
+    }
+
+    /// from is only a keyword in front of a module path, so it stays usable as a name
+    fn is_selective_import(&self) -> bool {
+        self.current().lexeme == "from"
+            && matches!(
+                self.token_type_at(self.current + 1),
+                TokenType::Literal(LiteralType::LoxString(_))
+            )
+    }
+
+    /// test is only a keyword in front of a test name and its block
+    fn is_test_block(&self) -> bool {
+        self.current().lexeme == "test"
+            && matches!(
+                self.token_type_at(self.current + 1),
+                TokenType::Literal(LiteralType::LoxString(_))
+            )
+            && self.token_type_at(self.current + 2) == &TokenType::LeftBrace
+    }
+
+    // test "name" { ... }, the body only runs under the test runner
+    fn test_declaration(&mut self) {
+        self.advance();
+        if self.states.len() > 1 || self.state().scope_depth > 0 {
+            self.error("Test blocks must be at the top level.");
+        }
+
+        self.advance();
+        let name = match &self.previous().token_type {
+            TokenType::Literal(LiteralType::LoxString(name)) => Rc::from(&name[1..name.len() - 1]),
+            _ => unreachable!(),
+        };
+        self.advance();
+        self.begin_function(Rc::from("test"), FunctionType::Function);
+        self.block();
+        self.end_closure();
+        let constant = self.make_constant(Value::LoxString(name));
+        self.emit_op_byte(OpCode::DefineTest, constant);
+    }
+
+    fn match_contextual_keyword(&mut self, keyword: &str) -> bool {
+        let is_identifier = matches!(
+            self.current().token_type,
+            TokenType::Literal(LiteralType::Identifier(_))
+        );
+        if is_identifier && self.current().lexeme == keyword {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Consumes the string naming a module, returns its constant
+    fn module_path(&mut self, message: &str) -> u8 {
+        if let TokenType::Literal(LiteralType::LoxString(path)) = &self.current().token_type {
+            let path = Rc::from(&path[1..path.len() - 1]);
+            self.advance();
+            self.make_constant(Value::LoxString(path))
+        } else {
+            self.error_at_current(message);
+            0
+        }
+    }
+
+    fn var_declaration(&mut self) {
+        let global = self.parse_variable("Expect variable name.");
+
+        if self.match_token(&TokenType::Equal) {
+            self.expression();
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.consume(
+            &TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        );
+
+        self.define_variable(global);
+    }
+
+    fn const_declaration(&mut self) {
+        let global = self.parse_variable("Expect constant name.");
+        let (name, declaration) = (self.previous_name(), self.previous);
+        self.consume(&TokenType::Equal, "Expect '=' after constant name.");
+        self.expression();
+        self.consume(
+            &TokenType::Semicolon,
+            "Expect ';' after constant declaration.",
+        );
+
+        if self.state().scope_depth > 0 {
+            self.state_mut().locals.last_mut().unwrap().constant = Some(declaration);
+            self.mark_initialized();
+        } else {
+            self.constants.insert(name, declaration);
+            self.emit_op_byte(OpCode::DefineConstant, global);
+        }
+    }
+
+    fn statement(&mut self) {
+        if self.match_token(&TokenType::Print) {
+            self.print_statement();
+        } else if self.match_token(&TokenType::For) {
+            self.for_statement();
+        } else if self.match_token(&TokenType::If) {
+            self.if_statement();
+        } else if self.match_token(&TokenType::Return) {
+            self.return_statement();
+        } else if self.match_token(&TokenType::While) {
+            self.while_statement();
+        } else if self.match_token(&TokenType::Match) {
+            self.match_statement();
+        } else if self.match_token(&TokenType::Throw) {
+            self.throw_statement();
+        } else if self.match_token(&TokenType::Assert) {
+            self.assert_statement();
+        } else if self.match_token(&TokenType::Yield) {
+            self.yield_statement();
+        } else if self.match_token(&TokenType::Try) {
+            self.try_statement();
+        } else if self.match_token(&TokenType::LeftBrace) {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+        } else {
+            self.expression_statement();
+        }
+    }
+
+    fn print_statement(&mut self) {
+        self.expression();
+        self.consume(&TokenType::Semicolon, "Expect ';' after value.");
+        self.emit_op(OpCode::Print);
+    }
+
+    fn for_statement(&mut self) {
+        self.begin_scope();
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'for'.");
+        if self.check(&TokenType::Var) && self.token_type_at(self.current + 2) == &TokenType::In {
+            self.for_in_statement();
+            self.end_scope();
+            return;
+        }
+
+        if self.match_token(&TokenType::Semicolon) {
+            // no initializer
+        } else if self.match_token(&TokenType::Var) {
+            self.var_declaration();
+        } else {
+            self.expression_statement();
+        }
+
+        let mut loop_start = self.chunk().code.len();
+        let mut exit_jump = None;
+        if !self.match_token(&TokenType::Semicolon) {
+            self.expression();
+            self.consume(&TokenType::Semicolon, "Expect ';' after loop condition.");
+
+            exit_jump = Some(self.emit_jump(OpCode::JumpIfFalse));
+            self.emit_op(OpCode::Pop);
+        }
+
+        if !self.match_token(&TokenType::RightParen) {
+            // the increment runs after the body, so jump over it now and loop back to it later
+            let body_jump = self.emit_jump(OpCode::Jump);
+            let increment_start = self.chunk().code.len();
+            self.expression();
+            self.emit_op(OpCode::Pop);
+            self.consume(&TokenType::RightParen, "Expect ')' after for clauses.");
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement();
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_op(OpCode::Pop);
+        }
+        self.end_scope();
+    }
+
+    // for (var name in iterable) statement, the '(' is already consumed
+    fn for_in_statement(&mut self) {
+        self.advance();
+        let name = self.consume_identifier("Expect variable name.");
+        self.consume(&TokenType::In, "Expect 'in' after loop variable.");
+        self.expression();
+        self.emit_op(OpCode::GetIter);
+        let iterator_slot = self.state().locals.len() as u8;
+        self.add_local(Rc::from(""));
+        self.mark_initialized();
+        self.consume(&TokenType::RightParen, "Expect ')' after for clauses.");
+
+        let loop_start = self.chunk().code.len();
+        self.emit_op_byte(OpCode::IterNext, iterator_slot);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        let next_exit = self.chunk().code.len() - 2;
+        let check_exit = self.emit_jump(OpCode::IterCheck);
+
+        // a new scope each time round, so closures capture that iteration's value
+        self.begin_scope();
+        self.add_local(name);
+        self.mark_initialized();
+        self.statement();
+        self.end_scope();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(next_exit);
+        self.patch_jump(check_exit);
+    }
+
+    fn if_statement(&mut self) {
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'if'.");
+        self.expression();
+        self.consume(&TokenType::RightParen, "Expect ')' after condition.");
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.statement();
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump);
+        self.emit_op(OpCode::Pop);
+
+        if self.match_token(&TokenType::Else) {
+            self.statement();
+        }
+        self.patch_jump(else_jump);
+    }
+
+    fn return_statement(&mut self) {
+        if self.state().function_type == FunctionType::Script {
+            self.error("Can't return from top-level code.");
+        }
+
+        if self.match_token(&TokenType::Semicolon) {
+            self.emit_implicit_return_value();
+        } else {
+            if self.state().function_type == FunctionType::Initializer {
+                self.error("Can't return a value from an initializer.");
+            }
+
+            self.expression();
+            self.consume(&TokenType::Semicolon, "Expect ';' after return value.");
+        }
+        self.emit_return_value();
+    }
+
+    /// Returns the value on top of the stack, through the finally blocks of enclosing try statements
+    fn emit_return_value(&mut self) {
+        let state = self.state();
+        let target = match state.tries.iter().rposition(|t| t.finally_slot.is_some()) {
+            Some(target) => target,
+            None => {
+                self.emit_op(OpCode::Return);
+                return;
+            }
+        };
+
+        let handlers: usize = state.tries[target..].iter().map(|t| t.handlers).sum();
+        let slot = state.tries[target].finally_slot.unwrap();
+        let local_count = state.tries[target].local_count;
+        let captured: Vec<bool> = state.locals[local_count..]
+            .iter()
+            .map(|local| local.is_captured)
+            .collect();
+
+        self.emit_op_byte(OpCode::SetLocal, slot);
+        self.emit_op(OpCode::Pop);
+        self.emit_constant(Value::Number(COMPLETED_BY_RETURN));
+        self.emit_op_byte(OpCode::SetLocal, slot + 1);
+        self.emit_op(OpCode::Pop);
+        for _ in 0..handlers {
+            self.emit_op(OpCode::PopHandler);
+        }
+        // leave the scopes inside the try, the compiler keeps them since code after this is dead
+        for is_captured in captured.into_iter().rev() {
+            if is_captured {
+                self.emit_op(OpCode::CloseUpvalue);
+            } else {
+                self.emit_op(OpCode::Pop);
+            }
+        }
+
+        let jump = self.emit_jump(OpCode::Jump);
+        self.state_mut().tries[target].returns.push(jump);
+    }
+
+    fn throw_statement(&mut self) {
+        self.expression();
+        self.consume(&TokenType::Semicolon, "Expect ';' after thrown value.");
+        self.emit_op(OpCode::Throw);
+    }
+
+    // assert condition, message; the error quotes the condition and where it is
+    fn assert_statement(&mut self) {
+        let first = self.current;
+        self.expression();
+        let condition = self.source_span(first, self.previous);
+
+        let fail_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        let end_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(fail_jump);
+        self.emit_op(OpCode::Pop);
+        // the message is only evaluated when the assertion fails
+        if self.match_token(&TokenType::Comma) {
+            self.expression();
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+        self.consume(&TokenType::Semicolon, "Expect ';' after assertion.");
+        let constant = self.make_constant(Value::LoxString(Rc::from(condition)));
+        self.emit_op_byte(OpCode::AssertFail, constant);
+        self.patch_jump(end_jump);
+    }
+
+    /// The source text from token first through token last, followed by its line and columns
+    fn source_span(&self, first: usize, last: usize) -> String {
+        let (first, last) = (&self.tokens[first], &self.tokens[last.max(first)]);
+        let start = first.offset;
+        let end = (last.offset + last.lexeme.len()).max(start);
+        let text = &self.source[start..end];
+        if first.line == last.line {
+            // columns count chars from 1, the span includes its last char
+            let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+            let column = self.source[line_start..start].chars().count() + 1;
+            let end_column = column + text.chars().count().max(1) - 1;
+            format!(
+                "{} (line {}, columns {}-{})",
+                text, first.line, column, end_column
+            )
+        } else {
+            format!("{} (lines {}-{})", text, first.line, last.line)
+        }
+    }
+
+    // any yield makes the function it is in a generator
+    fn yield_statement(&mut self) {
+        match self.state().function_type {
+            FunctionType::Script => self.error("Can't yield from top-level code."),
+            FunctionType::Initializer => self.error("Can't yield from an initializer."),
+            FunctionType::Function | FunctionType::Method => {}
+        }
+
+        self.expression();
+        self.consume(&TokenType::Semicolon, "Expect ';' after yielded value.");
+        self.emit_op(OpCode::Yield);
+        self.state_mut().function.is_generator = true;
+    }
+
+    fn try_statement(&mut self) {
+        let (has_catch, has_finally) = self.try_clauses();
+        if !has_catch && !has_finally {
+            self.error("Expect 'catch' or 'finally' after try block.");
+        }
+
+        self.begin_scope();
+        let mut finally_slot = None;
+        let mut finally_handler = None;
+        if has_finally {
+            finally_slot = Some(self.state().locals.len() as u8);
+            for _ in 0..2 {
+                self.emit_op(OpCode::Nil);
+                self.add_local(Rc::from(""));
+                self.mark_initialized();
+            }
+            finally_handler = Some(self.emit_jump(OpCode::PushHandler));
+        }
+        let catch_handler = if has_catch {
+            Some(self.emit_jump(OpCode::PushHandler))
+        } else {
+            None
+        };
+
+        let local_count = self.state().locals.len();
+        self.state_mut().tries.push(TryState {
+            handlers: has_finally as usize + has_catch as usize,
+            finally_slot,
+            local_count,
+            returns: Vec::new(),
+        });
+
+        self.consume(&TokenType::LeftBrace, "Expect '{' after 'try'.");
+        self.begin_scope();
+        self.block();
+        self.end_scope();
+
+        if let Some(catch_handler) = catch_handler {
+            self.emit_op(OpCode::PopHandler);
+            self.state_mut().tries.last_mut().unwrap().handlers -= 1;
+            let skip_catch = self.emit_jump(OpCode::Jump);
+
+            // the vm pushes the thrown value, which becomes the catch variable
+            self.patch_jump(catch_handler);
+            self.consume(&TokenType::Catch, "Expect 'catch' after try block.");
+            self.consume(&TokenType::LeftParen, "Expect '(' after 'catch'.");
+            let name = self.consume_identifier("Expect error variable name.");
+            self.consume(&TokenType::RightParen, "Expect ')' after error variable.");
+            self.consume(&TokenType::LeftBrace, "Expect '{' before catch body.");
+            self.begin_scope();
+            self.add_local(name);
+            self.mark_initialized();
+            self.block();
+            self.end_scope();
+            self.patch_jump(skip_catch);
+        }
+
+        let try_state = self.state_mut().tries.pop().unwrap();
+        if let (Some(slot), Some(finally_handler)) = (finally_slot, finally_handler) {
+            self.emit_op(OpCode::PopHandler);
+            let skip_throw = self.emit_jump(OpCode::Jump);
+
+            self.patch_jump(finally_handler);
+            self.emit_op_byte(OpCode::SetLocal, slot);
+            self.emit_op(OpCode::Pop);
+            self.emit_constant(Value::Number(COMPLETED_BY_THROW));
+            self.emit_op_byte(OpCode::SetLocal, slot + 1);
+            self.emit_op(OpCode::Pop);
+
+            self.patch_jump(skip_throw);
+            for jump in try_state.returns {
+                self.patch_jump(jump);
+            }
+            self.consume(&TokenType::Finally, "Expect 'finally' after try block.");
+            self.consume(&TokenType::LeftBrace, "Expect '{' after 'finally'.");
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+
+            // carry on with the throw or return the finally block interrupted
+            let not_thrown = self.emit_completion_check(slot, COMPLETED_BY_THROW);
+            self.emit_op_byte(OpCode::GetLocal, slot);
+            self.emit_op(OpCode::Throw);
+            self.patch_jump(not_thrown);
+            self.emit_op(OpCode::Pop);
+
+            let not_returned = self.emit_completion_check(slot, COMPLETED_BY_RETURN);
+            self.emit_op_byte(OpCode::GetLocal, slot);
+            self.emit_return_value();
+            self.patch_jump(not_returned);
+            self.emit_op(OpCode::Pop);
+        }
+        self.end_scope();
+    }
+
+    // jumps when the try didn't complete this way, the comparison is popped on both paths
+    fn emit_completion_check(&mut self, slot: u8, completion: f64) -> usize {
+        self.emit_op_byte(OpCode::GetLocal, slot + 1);
+        self.emit_constant(Value::Number(completion));
+        self.emit_op(OpCode::Equal);
+        let jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        jump
+    }
+
+    /// Looks past the try block at the current '{' for its catch and finally clauses
+    fn try_clauses(&self) -> (bool, bool) {
+        let mut index = self.closing_index(self.current) + 1;
+        let has_catch = self.token_type_at(index) == &TokenType::Catch;
+        if has_catch {
+            // catch ( name ) { ... }
+            index = self.closing_index(index + 1) + 1;
+            index = self.closing_index(index) + 1;
+        }
+        let has_finally = self.token_type_at(index) == &TokenType::Finally;
+        (has_catch, has_finally)
+    }
+
+    fn while_statement(&mut self) {
+        let loop_start = self.chunk().code.len();
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'while'.");
+        self.expression();
+        self.consume(&TokenType::RightParen, "Expect ')' after condition.");
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        self.statement();
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn expression_statement(&mut self) {
+        self.expression();
+        self.consume(&TokenType::Semicolon, "Expect ';' after expression.");
+        self.emit_op(OpCode::Pop);
+    }
+
+    fn block(&mut self) {
+        while !self.check(&TokenType::RightBrace) && !self.check(&TokenType::EOF) {
+            self.declaration();
+        }
+
+        self.consume(&TokenType::RightBrace, "Expect '}' after block.");
+    }
+
+    fn expression(&mut self) {
+        self.parse_precedence(Precedence::Assignment);
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) {
+        self.advance();
+        let prefix = match get_rule(&self.previous().token_type).prefix {
+            Some(prefix) => prefix,
+            None => {
+                self.error("Expect expression.");
+                return;
+            }
+        };
+
+        // only the lowest precedence expression can be an assignment target
+        let can_assign = precedence <= Precedence::Assignment;
+        prefix(self, can_assign);
+
+        while precedence <= get_rule(&self.current().token_type).precedence {
+            self.advance();
+            if let Some(infix) = get_rule(&self.previous().token_type).infix {
+                infix(self, can_assign);
+            }
+        }
+
+        if can_assign && self.match_token(&TokenType::Equal) {
+            self.error("Invalid assignment target.");
+        }
+    }
+
+    fn grouping(&mut self, _can_assign: bool) {
+        if self.is_arrow_function() {
+            self.arrow_function();
+            return;
+        }
+
+        self.expression();
+        self.consume(&TokenType::RightParen, "Expect ')' after expression.");
+    }
+
+    // `fun (a, b) { ... }` in expression position
+    fn lambda(&mut self, _can_assign: bool) {
+        self.begin_function(Rc::from(ANONYMOUS_NAME), FunctionType::Function);
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'fun'.");
+        self.parameters();
+        self.consume(&TokenType::LeftBrace, "Expect '{' before function body.");
+        self.block();
+        self.end_closure();
+    }
+
+    // `(a, b) => expression`, the '(' is already consumed
+    fn arrow_function(&mut self) {
+        self.begin_function(Rc::from(ANONYMOUS_NAME), FunctionType::Function);
+        self.parameters();
+        self.consume(&TokenType::Arrow, "Expect '=>' after parameters.");
+        self.expression();
+        self.emit_op(OpCode::Return);
+        self.end_closure();
+    }
+
+    /// Looks past the parenthesis just consumed for the '=>' that makes it a parameter list
+    fn is_arrow_function(&self) -> bool {
+        let close = self.closing_index(self.previous);
+        self.token_type_at(close + 1) == &TokenType::Arrow
+    }
+
+    /// Index of the bracket closing the one at open, or of EOF when it is never closed
+    fn closing_index(&self, open: usize) -> usize {
+        let mut depth = 0;
+        let mut index = open;
+        loop {
+            match self.token_type_at(index) {
+                TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return index;
+                    }
+                }
+                TokenType::EOF => return index,
+                _ => {}
+            }
+            index += 1;
+        }
+    }
+
+    fn call(&mut self, _can_assign: bool) {
+        let (positional, named) = self.argument_list();
+        if named == 0 {
+            self.emit_op_byte(OpCode::Call, positional);
+        } else {
+            self.emit_op_byte(OpCode::CallNamed, positional);
+            self.emit_byte(named);
+        }
+    }
+
+    fn dot(&mut self, can_assign: bool) {
+        let name = self.consume_identifier("Expect property name after '.'.");
+        let name_constant = self.identifier_constant(name);
+
+        if can_assign && self.match_token(&TokenType::Equal) {
+            self.expression();
+            self.emit_op_byte(OpCode::SetProperty, name_constant);
+        } else if !self.has_named_arguments() && self.match_token(&TokenType::LeftParen) {
+            let (arg_count, _) = self.argument_list();
+            self.emit_op_byte(OpCode::Invoke, name_constant);
+            self.emit_byte(arg_count);
+        } else {
+            // named arguments go through a plain call of the bound method
+            self.emit_op_byte(OpCode::GetProperty, name_constant);
+        }
+    }
+
+    fn index(&mut self, can_assign: bool) {
+        self.expression();
+        self.consume(&TokenType::RightBracket, "Expect ']' after index.");
+
+        if can_assign && self.match_token(&TokenType::Equal) {
+            self.expression();
+            self.emit_op(OpCode::SetIndex);
+        } else {
+            self.emit_op(OpCode::GetIndex);
+        }
+    }
+
+    fn list(&mut self, _can_assign: bool) {
+        let mut element_count: usize = 0;
+        while !self.check(&TokenType::RightBracket) && !self.check(&TokenType::EOF) {
+            self.expression();
+            if element_count == 255 {
+                self.error("Can't have more than 255 elements in a list literal.");
+            }
+            element_count += 1;
+
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+
+        self.consume(&TokenType::RightBracket, "Expect ']' after list elements.");
+        self.emit_op_byte(OpCode::BuildList, element_count as u8);
+    }
+
+    // a '{' that starts a statement is a block, so a map literal only ever shows up here
+    fn map(&mut self, _can_assign: bool) {
+        let mut entry_count: usize = 0;
+        while !self.check(&TokenType::RightBrace) && !self.check(&TokenType::EOF) {
+            self.expression();
+            self.consume(&TokenType::Colon, "Expect ':' after map key.");
+            self.expression();
+            if entry_count == 255 {
+                self.error("Can't have more than 255 entries in a map literal.");
+            }
+            entry_count += 1;
+
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+
+        self.consume(&TokenType::RightBrace, "Expect '}' after map entries.");
+        self.emit_op_byte(OpCode::BuildMap, entry_count as u8);
+    }
+
+    fn unary(&mut self, _can_assign: bool) {
+        let operator = self.previous().token_type.clone();
+        self.parse_precedence(Precedence::Unary);
+
+        match operator {
+            TokenType::Bang => self.emit_op(OpCode::Not),
+            TokenType::Minus => self.emit_op(OpCode::Negate),
+            _ => unreachable!(),
+        }
+    }
+
+    fn binary(&mut self, _can_assign: bool) {
+        let operator = self.previous().token_type.clone();
+        let rule = get_rule(&operator);
+        self.parse_precedence(rule.precedence.next());
+
+        match operator {
+            TokenType::BangEqual => {
+                self.emit_op(OpCode::Equal);
+                self.emit_op(OpCode::Not);
+            }
+            TokenType::EqualEqual => self.emit_op(OpCode::Equal),
+            TokenType::Greater => self.emit_op(OpCode::Greater),
+            TokenType::GreaterEqual => {
+                self.emit_op(OpCode::Less);
+                self.emit_op(OpCode::Not);
+            }
+            TokenType::Less => self.emit_op(OpCode::Less),
+            TokenType::LessEqual => {
+                self.emit_op(OpCode::Greater);
+                self.emit_op(OpCode::Not);
+            }
+            TokenType::Plus => self.emit_op(OpCode::Add),
+            TokenType::Minus => self.emit_op(OpCode::Subtract),
+            TokenType::Star => self.emit_op(OpCode::Multiply),
+            TokenType::Slash => self.emit_op(OpCode::Divide),
+            TokenType::DotDot => self.emit_op_byte(OpCode::BuildRange, 0),
+            TokenType::DotDotEqual => self.emit_op_byte(OpCode::BuildRange, 1),
+            _ => unreachable!(),
+        }
+    }
+
+    fn and(&mut self, _can_assign: bool) {
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+
+        self.emit_op(OpCode::Pop);
+        self.parse_precedence(Precedence::And);
+
+        self.patch_jump(end_jump);
+    }
+
+    fn or(&mut self, _can_assign: bool) {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(else_jump);
+        self.emit_op(OpCode::Pop);
+
+        self.parse_precedence(Precedence::Or);
+        self.patch_jump(end_jump);
+    }
+
+    fn variable(&mut self, can_assign: bool) {
+        let name = self.previous_name();
+        self.named_variable(name, can_assign);
+    }
+
+    fn string(&mut self, _can_assign: bool) {
+        if let TokenType::Literal(LiteralType::LoxString(value)) = &self.previous().token_type {
+            // the scanned literal keeps its quotes
+            let value = Rc::from(&value[1..value.len() - 1]);
+            self.emit_constant(Value::LoxString(value));
+        }
+    }
+
+    fn number(&mut self, _can_assign: bool) {
+        let value = match self.previous().token_type {
+            TokenType::Literal(LiteralType::Integer(value)) => value as f64,
+            TokenType::Literal(LiteralType::Float(value)) => value,
+            _ => unreachable!(),
+        };
+
+        self.emit_constant(Value::Number(value));
+    }
+
+    fn literal(&mut self, _can_assign: bool) {
+        match self.previous().token_type {
+            TokenType::False => self.emit_op(OpCode::False),
+            TokenType::Nil => self.emit_op(OpCode::Nil),
+            TokenType::True => self.emit_op(OpCode::True),
+            _ => unreachable!(),
+        }
+    }
+
+    fn this(&mut self, _can_assign: bool) {
+        if self.classes.is_empty() {
+            self.error("Can't use 'this' outside of a class.");
+            return;
+        }
+
+        self.variable(false);
+    }
+
+    fn super_(&mut self, _can_assign: bool) {
+        match self.classes.last() {
+            None => self.error("Can't use 'super' outside of a class."),
+            Some(class) if !class.has_superclass => {
+                self.error("Can't use 'super' in a class with no superclass.")
+            }
+            Some(_) => {}
+        }
+
+        self.consume(&TokenType::Dot, "Expect '.' after 'super'.");
+        let name = self.consume_identifier("Expect superclass method name.");
+        let name_constant = self.identifier_constant(name);
+
+        self.named_variable(Rc::from("this"), false);
+        if !self.has_named_arguments() && self.match_token(&TokenType::LeftParen) {
+            let (arg_count, _) = self.argument_list();
+            self.named_variable(Rc::from("super"), false);
+            self.emit_op_byte(OpCode::SuperInvoke, name_constant);
+            self.emit_byte(arg_count);
+        } else {
+            self.named_variable(Rc::from("super"), false);
+            self.emit_op_byte(OpCode::GetSuper, name_constant);
+        }
+    }
+
+    fn named_variable(&mut self, name: Rc<str>, can_assign: bool) {
+        let name_index = self.previous;
+        let current = self.states.len() - 1;
+        let (get_op, set_op, arg) = if let Some(slot) = self.resolve_local(current, &name) {
+            (OpCode::GetLocal, OpCode::SetLocal, slot)
+        } else if let Some(index) = self.resolve_upvalue(current, &name) {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, index)
+        } else {
+            let constant = self.identifier_constant(name.clone());
+            (OpCode::GetGlobal, OpCode::SetGlobal, constant)
+        };
+
+        if can_assign && self.match_token(&TokenType::Equal) {
+            if let Some(declaration) = self.constant_declaration(&name) {
+                let message = format!(
+                    "Can't assign to constant '{}', declared on line {}.",
+                    name, self.tokens[declaration].line
+                );
+                self.error_at(name_index, &message);
+            }
+            self.expression();
+            self.emit_op_byte(set_op, arg);
+        } else {
+            self.emit_op_byte(get_op, arg);
+        }
+    }
+
+    /// Compiles the arguments of a call, returns the positional and named argument counts
+    ///
+    /// Each named argument is pushed as its name followed by its value, after the positional ones.
+    fn argument_list(&mut self) -> (u8, u8) {
+        let mut positional: usize = 0;
+        let mut named: usize = 0;
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if self.is_named_argument() {
+                    let name = self.consume_identifier("Expect argument name.");
+                    self.advance();
+                    self.emit_constant(Value::LoxString(name));
+                    self.expression();
+                    named += 1;
+                } else {
+                    if named > 0 {
+                        self.error_at_current("Positional arguments can't follow named ones.");
+                    }
+                    self.expression();
+                    positional += 1;
+                }
+
+                if positional + named == 256 {
+                    self.error("Can't have more than 255 arguments.");
+                }
+
+                if !self.match_token(&TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(&TokenType::RightParen, "Expect ')' after arguments.");
+        (positional as u8, named as u8)
+    }
+
+    fn is_named_argument(&self) -> bool {
+        matches!(
+            self.current().token_type,
+            TokenType::Literal(LiteralType::Identifier(_))
+        ) && self.token_type_at(self.current + 1) == &TokenType::Colon
+    }
+
+    /// Looks through the argument list starting at the current '(' for a named argument
+    fn has_named_arguments(&self) -> bool {
+        if !self.check(&TokenType::LeftParen) {
+            return false;
+        }
+
+        let mut depth = 0;
+        let mut index = self.current;
+        loop {
+            match self.token_type_at(index) {
+                TokenType::LeftParen | TokenType::LeftBracket | TokenType::LeftBrace => depth += 1,
+                TokenType::RightParen | TokenType::RightBracket | TokenType::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return false;
+                    }
+                }
+                TokenType::Literal(LiteralType::Identifier(_))
+                    if depth == 1 && self.token_type_at(index + 1) == &TokenType::Colon =>
+                {
+                    return true
+                }
+                TokenType::EOF => return false,
+                _ => {}
+            }
+            index += 1;
+        }
+    }
+
+    /// Declares the identifier just consumed, returns its name constant for globals
+    fn parse_variable(&mut self, message: &str) -> u8 {
+        let name = self.consume_identifier(message);
+
+        self.declare_variable(name.clone());
+        if self.state().scope_depth > 0 {
+            return 0;
+        }
+
+        self.identifier_constant(name)
+    }
+
+    fn declare_variable(&mut self, name: Rc<str>) {
+        let state = self.state();
+        if state.scope_depth == 0 {
+            return;
+        }
+
+        let already_declared = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= state.scope_depth))
+            .any(|local| local.name == name);
+        if already_declared {
+            self.error("Already a variable with this name in this scope.");
+        }
+
+        self.add_local(name);
+    }
+
+    fn add_local(&mut self, name: Rc<str>) {
+        if self.state().locals.len() == LOCALS_MAX {
+            self.error("Too many local variables in function.");
+            return;
+        }
+
+        self.state_mut().locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+            constant: None,
+        });
+    }
+
+    fn define_variable(&mut self, global: u8) {
+        if self.state().scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+
+        self.emit_op_byte(OpCode::DefineGlobal, global);
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.state_mut();
+        if state.scope_depth == 0 {
+            return;
+        }
+
+        let depth = state.scope_depth;
+        state.locals.last_mut().unwrap().depth = Some(depth);
+    }
+
+    /// Looks name up among the locals of the function at state_index
+    fn resolve_local(&mut self, state_index: usize, name: &str) -> Option<u8> {
+        let (slot, depth) = self.states[state_index]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| &*local.name == name)
+            .map(|(slot, local)| (slot, local.depth))?;
+
+        if depth.is_none() {
+            self.error("Can't read local variable in its own initializer.");
+        }
+        Some(slot as u8)
+    }
+
+    /// Where the variable name resolves to was declared, if it is a constant
+    fn constant_declaration(&self, name: &str) -> Option<usize> {
+        // the same order names resolve in, locals from the innermost function out then globals
+        for state in self.states.iter().rev() {
+            if let Some(local) = state.locals.iter().rev().find(|local| &*local.name == name) {
+                return local.constant;
+            }
+        }
+        self.constants.get(name).copied()
+    }
+
+    /// Looks name up in the enclosing functions, threading an upvalue through each one in between
+    fn resolve_upvalue(&mut self, state_index: usize, name: &str) -> Option<u8> {
+        if state_index == 0 {
+            return None;
+        }
+        let enclosing = state_index - 1;
+
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.states[enclosing].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(state_index, slot, true));
+        }
+
+        let index = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(state_index, index, false))
+    }
+
+    fn add_upvalue(&mut self, state_index: usize, index: u8, is_local: bool) -> u8 {
+        let upvalue = Upvalue { index, is_local };
+        let upvalues = &self.states[state_index].upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing as u8;
+        }
+
+        if upvalues.len() == UPVALUES_MAX {
+            self.error("Too many closure variables in function.");
+            return 0;
+        }
+
+        let state = &mut self.states[state_index];
+        state.upvalues.push(upvalue);
+        state.function.upvalue_count = state.upvalues.len();
+        (state.upvalues.len() - 1) as u8
+    }
+
+    fn begin_scope(&mut self) {
+        self.state_mut().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        self.state_mut().scope_depth -= 1;
+
+        loop {
+            let state = self.state();
+            let is_captured = match state.locals.last() {
+                Some(local) if local.depth.is_some_and(|depth| depth > state.scope_depth) => {
+                    local.is_captured
+                }
+                _ => break,
+            };
+
+            if is_captured {
+                self.emit_op(OpCode::CloseUpvalue);
+            } else {
+                self.emit_op(OpCode::Pop);
+            }
+            self.state_mut().locals.pop();
+        }
+    }
+
+    fn end_function(&mut self) -> Function {
+        self.emit_return();
+        self.states.pop().unwrap().function
+    }
+
+    fn identifier_constant(&mut self, name: Rc<str>) -> u8 {
+        self.make_constant(Value::LoxString(name))
+    }
+
+    fn make_constant(&mut self, value: Value) -> u8 {
+        let constant = self.chunk_mut().add_constant(value);
+        if constant > u8::MAX as usize {
+            self.error("Too many constants in one chunk.");
+            return 0;
+        }
+
+        constant as u8
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let constant = self.make_constant(value);
+        self.emit_op_byte(OpCode::Constant, constant);
+    }
+
+    fn emit_return(&mut self) {
+        self.emit_implicit_return_value();
+        self.emit_op(OpCode::Return);
+    }
+
+    fn emit_implicit_return_value(&mut self) {
+        // initializers always hand back the new instance
+        if self.state().function_type == FunctionType::Initializer {
+            self.emit_op_byte(OpCode::GetLocal, 0);
+        } else {
+            self.emit_op(OpCode::Nil);
+        }
+    }
+
+    /// Emits a jump with a placeholder offset, returns where to patch it
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        self.chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        // -2 to adjust for the jump offset itself
+        let jump = self.chunk().code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            self.error("Too much code to jump over.");
+        }
+
+        let bytes = (jump as u16).to_be_bytes();
+        self.chunk_mut().code[offset] = bytes[0];
+        self.chunk_mut().code[offset + 1] = bytes[1];
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+
+        // +2 to also jump back over the loop's own offset
+        let offset = self.chunk().code.len() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            self.error("Loop body too large.");
+        }
+
+        let bytes = (offset as u16).to_be_bytes();
+        self.emit_byte(bytes[0]);
+        self.emit_byte(bytes[1]);
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.previous().line;
+        self.chunk_mut().write(byte, line);
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    fn emit_op_byte(&mut self, op: OpCode, byte: u8) {
+        self.emit_op(op);
+        self.emit_byte(byte);
+    }
+
+    fn state(&self) -> &FunctionState {
+        self.states.last().unwrap()
+    }
+
+    fn state_mut(&mut self) -> &mut FunctionState {
+        self.states.last_mut().unwrap()
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.state().function.chunk
+    }
+
+    fn chunk_mut(&mut self) -> &mut Chunk {
+        &mut self.state_mut().function.chunk
+    }
+
+    fn synchronize(&mut self) {
+        self.panic_mode = false;
+
+        while self.current().token_type != TokenType::EOF {
+            if self.previous().token_type == TokenType::Semicolon {
+                return;
+            }
+
+            match self.current().token_type {
+                TokenType::Class
+                | TokenType::Trait
+                | TokenType::Const
+                | TokenType::Enum
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return
+                | TokenType::Throw
+                | TokenType::Assert
+                | TokenType::Yield
+                | TokenType::Try
+                | TokenType::Import
+                | TokenType::Match => return,
+                _ => self.advance(),
+            }
+        }
+    }
+
+    fn current(&self) -> &Token {
+        &self.tokens[self.current]
+    }
+
+    fn previous(&self) -> &Token {
+        &self.tokens[self.previous]
+    }
+
+    /// Type of the token at index, or EOF past the end
+    fn token_type_at(&self, index: usize) -> &TokenType {
+        let index = index.min(self.tokens.len() - 1);
+        &self.tokens[index].token_type
+    }
+
+    /// Name of the identifier or keyword just consumed
+    fn previous_name(&self) -> Rc<str> {
+        Rc::from(self.previous().lexeme.as_str())
+    }
+
+    fn advance(&mut self) {
+        self.previous = self.current;
+        // stay on EOF once we reach it
+        if self.current < self.tokens.len() - 1 {
+            self.current += 1;
+        }
+    }
+
+    fn check(&self, token_type: &TokenType) -> bool {
+        &self.current().token_type == token_type
+    }
+
+    fn match_token(&mut self, token_type: &TokenType) -> bool {
+        if !self.check(token_type) {
+            return false;
+        }
+
+        self.advance();
+        true
+    }
+
+    fn consume(&mut self, token_type: &TokenType, message: &str) {
+        if self.check(token_type) {
+            self.advance();
+            return;
+        }
+
+        self.error_at_current(message);
+    }
+
+    fn consume_identifier(&mut self, message: &str) -> Rc<str> {
+        if let TokenType::Literal(LiteralType::Identifier(_)) = self.current().token_type {
+            self.advance();
+        } else {
+            self.error_at_current(message);
+        }
+
+        self.previous_name()
+    }
+
+    fn error_at_current(&mut self, message: &str) {
+        self.error_at(self.current, message);
+    }
+
+    fn error(&mut self, message: &str) {
+        self.error_at(self.previous, message);
+    }
+
+    fn warning_at(&mut self, index: usize, message: &str) {
+        let token = &self.tokens[index];
+        let warning = Warning {
+            line: token.line,
+            location: format!(" at '{}'", token.lexeme),
+            message: String::from(message),
+        };
+        warn!("{}", warning);
+        self.warnings.push(warning);
+    }
+
+    fn error_at(&mut self, index: usize, message: &str) {
+        // only the first error until the parser resynchronizes is worth reporting
+        if self.panic_mode {
+            return;
+        }
+        self.panic_mode = true;
+
+        let token = &self.tokens[index];
+        let location = match token.token_type {
+            TokenType::EOF => String::from(" at end"),
+            _ => format!(" at '{}'", token.lexeme),
+        };
+
+        super::report(token.line, &location, message);
+        self.errors.push(SyntaxError {
+            line: token.line,
+            location,
+            message: String::from(message),
+        });
+    }
+}
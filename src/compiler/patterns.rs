@@ -0,0 +1,608 @@
+use std::rc::Rc;
+
+use super::{Compiler, FunctionType};
+use crate::chunk::OpCode;
+use crate::token::*;
+use crate::value::Value;
+
+// A match arm's pattern, parsed whole before any code for it is emitted
+enum Pattern {
+    /// `_`
+    Wildcard,
+    /// A name, binds the whole value
+    Binding(Rc<str>),
+    Literal(Value),
+    /// `1..5` leaves out the end, `1..=5` includes it
+    Range {
+        start: f64,
+        end: f64,
+        inclusive: bool,
+    },
+    /// `Point { x, y: 0 }`, an instance of the class or a subclass with those fields
+    Instance {
+        class: Rc<str>,
+        fields: Vec<(Rc<str>, Pattern)>,
+    },
+    /// `[first, _, ...rest]`, rest is None for a bare `...`
+    List {
+        elements: Vec<Pattern>,
+        rest: Option<Option<Rc<str>>>,
+    },
+    /// `Shape.Circle(r)`, or `Shape.Circle` for the variant whatever its values
+    Variant {
+        enum_name: Rc<str>,
+        variant: Rc<str>,
+        values: Option<Vec<Pattern>>,
+    },
+}
+
+impl Pattern {
+    /// Matches every value
+    fn is_catch_all(&self) -> bool {
+        matches!(self, Pattern::Wildcard | Pattern::Binding(_))
+    }
+}
+
+// One step from a matched value to a part of it
+#[derive(Clone)]
+enum PathStep {
+    Field(Rc<str>),
+    Index(usize),
+    /// The elements from this index on, as a new list
+    From(usize),
+    /// An enum variant's value by position
+    VariantValue(usize),
+}
+
+// The closed set of values every arm so far matches from, a match over one can miss some
+#[derive(PartialEq)]
+enum ClosedSet {
+    NoArms,
+    Booleans,
+    Variants(Rc<str>),
+    Open,
+}
+
+// What earlier arms already cover, for the unreachable and missing case warnings
+struct Coverage {
+    catch_all: bool,
+    literals: Vec<Value>,
+    /// Variants matched whatever their values are
+    variants: Vec<Rc<str>>,
+    set: ClosedSet,
+}
+
+impl Coverage {
+    fn new() -> Self {
+        Self {
+            catch_all: false,
+            literals: Vec::new(),
+            variants: Vec::new(),
+            set: ClosedSet::NoArms,
+        }
+    }
+}
+
+impl Compiler {
+    /// `match (value) { pattern => statement ... }`, does nothing when no arm matches
+    pub(super) fn match_statement(&mut self) {
+        let keyword = self.previous;
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'match'.");
+        self.begin_scope();
+        self.expression();
+        let slot = self.state().locals.len() as u8;
+        self.add_local(Rc::from(""));
+        self.mark_initialized();
+        self.consume(&TokenType::RightParen, "Expect ')' after match value.");
+
+        self.consume(&TokenType::LeftBrace, "Expect '{' before match arms.");
+        let mut coverage = Coverage::new();
+        let mut end_jumps = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.check(&TokenType::EOF) {
+            if let Some(end_jump) = self.match_arm(slot, false, &mut coverage) {
+                end_jumps.push(end_jump);
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after match arms.");
+
+        for end_jump in end_jumps {
+            self.patch_jump(end_jump);
+        }
+        self.end_scope();
+        self.warn_missing_cases(keyword, &coverage);
+    }
+
+    /// `match (value) { pattern => expression, ... }`, no arm matching is a runtime error
+    pub(super) fn match_expression(&mut self, _can_assign: bool) {
+        let keyword = self.previous;
+        self.consume(&TokenType::LeftParen, "Expect '(' after 'match'.");
+
+        // the arms are compiled into a function called with the value, so their bindings get
+        // stack slots of their own even when the match sits in the middle of an expression
+        let value_start = self.current;
+        self.current = self.closing_index(self.previous);
+        self.advance();
+
+        self.begin_function(Rc::from("match"), FunctionType::Function);
+        self.state_mut().function.arity = 1;
+        let slot = self.state().locals.len() as u8;
+        self.add_local(Rc::from(""));
+        self.mark_initialized();
+
+        self.consume(&TokenType::LeftBrace, "Expect '{' before match arms.");
+        let mut coverage = Coverage::new();
+        while !self.check(&TokenType::RightBrace) && !self.check(&TokenType::EOF) {
+            self.match_arm(slot, true, &mut coverage);
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after match arms.");
+        self.emit_op_byte(OpCode::GetLocal, slot);
+        self.emit_op(OpCode::NoMatch);
+        self.end_closure();
+        self.warn_missing_cases(keyword, &coverage);
+
+        // now the value, which goes after the function in the calling code
+        let (after_current, after_previous) = (self.current, self.previous);
+        self.current = value_start;
+        self.expression();
+        self.consume(&TokenType::RightParen, "Expect ')' after match value.");
+        self.current = after_current;
+        self.previous = after_previous;
+        self.emit_op_byte(OpCode::Call, 1);
+    }
+
+    // Expression arms return their value, statement arms return the jump to after the match
+    fn match_arm(
+        &mut self,
+        slot: u8,
+        is_expression: bool,
+        coverage: &mut Coverage,
+    ) -> Option<usize> {
+        let pattern_start = self.current;
+        let pattern = self.pattern();
+        self.begin_scope();
+
+        let mut fail_jumps = Vec::new();
+        self.pattern_test(slot, &mut Vec::new(), &pattern, &mut fail_jumps);
+        let bindings_start = self.state().locals.len();
+        self.pattern_bindings(slot, &mut Vec::new(), &pattern);
+
+        let guard_jump = if self.match_token(&TokenType::If) {
+            self.expression();
+            let guard_jump = self.emit_jump(OpCode::JumpIfFalse);
+            self.emit_op(OpCode::Pop);
+            Some(guard_jump)
+        } else {
+            None
+        };
+        self.check_reachable(pattern_start, &pattern, guard_jump.is_some(), coverage);
+
+        self.consume(&TokenType::Arrow, "Expect '=>' after match pattern.");
+        if is_expression {
+            self.expression();
+            self.emit_op(OpCode::Return);
+        } else {
+            self.statement();
+        }
+
+        let captured: Vec<bool> = self.state().locals[bindings_start..]
+            .iter()
+            .map(|local| local.is_captured)
+            .collect();
+        self.end_scope();
+        let end_jump = if is_expression {
+            None
+        } else {
+            Some(self.emit_jump(OpCode::Jump))
+        };
+
+        // a failed guard leaves the bindings to clean up before trying the next arm
+        let mut skip_fail = None;
+        if let Some(guard_jump) = guard_jump {
+            self.patch_jump(guard_jump);
+            self.emit_op(OpCode::Pop);
+            for is_captured in captured.into_iter().rev() {
+                if is_captured {
+                    self.emit_op(OpCode::CloseUpvalue);
+                } else {
+                    self.emit_op(OpCode::Pop);
+                }
+            }
+            skip_fail = Some(self.emit_jump(OpCode::Jump));
+        }
+
+        if !fail_jumps.is_empty() {
+            for fail_jump in fail_jumps {
+                self.patch_jump(fail_jump);
+            }
+            self.emit_op(OpCode::Pop);
+        }
+        if let Some(skip_fail) = skip_fail {
+            self.patch_jump(skip_fail);
+        }
+        end_jump
+    }
+
+    fn pattern(&mut self) -> Pattern {
+        match self.current().token_type.clone() {
+            TokenType::Literal(LiteralType::Identifier(_)) if self.current().lexeme == "_" => {
+                self.advance();
+                Pattern::Wildcard
+            }
+            TokenType::Literal(LiteralType::Identifier(_)) => {
+                let name = self.consume_identifier("Expect pattern.");
+                if self.match_token(&TokenType::LeftBrace) {
+                    self.instance_pattern(name)
+                } else if self.match_token(&TokenType::Dot) {
+                    self.variant_pattern(name)
+                } else {
+                    Pattern::Binding(name)
+                }
+            }
+            TokenType::LeftBracket => {
+                self.advance();
+                self.list_pattern()
+            }
+            TokenType::Literal(LiteralType::LoxString(value)) => {
+                self.advance();
+                // the scanned literal keeps its quotes
+                Pattern::Literal(Value::LoxString(Rc::from(&value[1..value.len() - 1])))
+            }
+            TokenType::True | TokenType::False | TokenType::Nil => {
+                self.advance();
+                Pattern::Literal(match self.previous().token_type {
+                    TokenType::True => Value::Bool(true),
+                    TokenType::False => Value::Bool(false),
+                    _ => Value::Nil,
+                })
+            }
+            TokenType::Minus | TokenType::Literal(_) => {
+                let start = self.number_pattern();
+                let inclusive = self.check(&TokenType::DotDotEqual);
+                if inclusive || self.check(&TokenType::DotDot) {
+                    self.advance();
+                    let end = self.number_pattern();
+                    Pattern::Range {
+                        start,
+                        end,
+                        inclusive,
+                    }
+                } else {
+                    Pattern::Literal(Value::Number(start))
+                }
+            }
+            _ => {
+                self.error_at_current("Expect pattern.");
+                Pattern::Wildcard
+            }
+        }
+    }
+
+    fn number_pattern(&mut self) -> f64 {
+        let sign = if self.match_token(&TokenType::Minus) {
+            -1.0
+        } else {
+            1.0
+        };
+
+        let number = match self.current().token_type {
+            TokenType::Literal(LiteralType::Integer(value)) => value as f64,
+            TokenType::Literal(LiteralType::Float(value)) => value,
+            _ => {
+                self.error_at_current("Expect number in pattern.");
+                return 0.0;
+            }
+        };
+        self.advance();
+        sign * number
+    }
+
+    // the class name and '{' are already consumed
+    fn instance_pattern(&mut self, class: Rc<str>) -> Pattern {
+        let mut fields = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.check(&TokenType::EOF) {
+            let field = self.consume_identifier("Expect field name.");
+            let pattern = if self.match_token(&TokenType::Colon) {
+                self.pattern()
+            } else {
+                Pattern::Binding(field.clone())
+            };
+            fields.push((field, pattern));
+
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightBrace, "Expect '}' after field patterns.");
+        Pattern::Instance { class, fields }
+    }
+
+    // the enum name and '.' are already consumed
+    fn variant_pattern(&mut self, enum_name: Rc<str>) -> Pattern {
+        let variant = self.consume_identifier("Expect variant name after '.'.");
+        let values = if self.match_token(&TokenType::LeftParen) {
+            let mut values = Vec::new();
+            while !self.check(&TokenType::RightParen) && !self.check(&TokenType::EOF) {
+                values.push(self.pattern());
+                if !self.match_token(&TokenType::Comma) {
+                    break;
+                }
+            }
+            self.consume(&TokenType::RightParen, "Expect ')' after variant patterns.");
+            Some(values)
+        } else {
+            None
+        };
+
+        Pattern::Variant {
+            enum_name,
+            variant,
+            values,
+        }
+    }
+
+    // the '[' is already consumed
+    fn list_pattern(&mut self) -> Pattern {
+        let mut elements = Vec::new();
+        let mut rest = None;
+        while !self.check(&TokenType::RightBracket) && !self.check(&TokenType::EOF) {
+            if self.match_token(&TokenType::Ellipsis) {
+                let is_named = matches!(
+                    self.current().token_type,
+                    TokenType::Literal(LiteralType::Identifier(_))
+                ) && self.current().lexeme != "_";
+                if is_named {
+                    rest = Some(Some(self.consume_identifier("Expect rest name.")));
+                } else {
+                    self.match_token(&TokenType::Literal(LiteralType::Identifier(String::from(
+                        "_",
+                    ))));
+                    rest = Some(None);
+                }
+                break;
+            }
+
+            elements.push(self.pattern());
+            if !self.match_token(&TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(&TokenType::RightBracket, "Expect ']' after list pattern.");
+        Pattern::List { elements, rest }
+    }
+
+    /// Emits the checks that the value at path matches pattern, each failed one jumps with false
+    /// left on the stack
+    fn pattern_test(
+        &mut self,
+        slot: u8,
+        path: &mut Vec<PathStep>,
+        pattern: &Pattern,
+        fail_jumps: &mut Vec<usize>,
+    ) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Binding(_) => {}
+            Pattern::Literal(value) => {
+                self.emit_path(slot, path);
+                self.emit_constant(value.clone());
+                self.emit_op(OpCode::Equal);
+                self.emit_test_jump(fail_jumps);
+            }
+            Pattern::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                self.emit_path(slot, path);
+                self.emit_constant(Value::Number(*start));
+                self.emit_constant(Value::Number(*end));
+                self.emit_op_byte(OpCode::MatchRange, *inclusive as u8);
+                self.emit_test_jump(fail_jumps);
+            }
+            Pattern::Instance { class, fields } => {
+                self.emit_path(slot, path);
+                self.named_variable(class.clone(), false);
+                self.emit_op(OpCode::MatchInstance);
+                self.emit_test_jump(fail_jumps);
+
+                for (field, pattern) in fields {
+                    self.emit_path(slot, path);
+                    let name_constant = self.identifier_constant(field.clone());
+                    self.emit_op_byte(OpCode::HasField, name_constant);
+                    self.emit_test_jump(fail_jumps);
+
+                    path.push(PathStep::Field(field.clone()));
+                    self.pattern_test(slot, path, pattern, fail_jumps);
+                    path.pop();
+                }
+            }
+            Pattern::List { elements, rest } => {
+                if elements.len() > u8::MAX as usize {
+                    self.error("Can't have more than 255 elements in a list pattern.");
+                }
+                self.emit_path(slot, path);
+                self.emit_op_byte(OpCode::MatchList, elements.len() as u8);
+                self.emit_byte(rest.is_some() as u8);
+                self.emit_test_jump(fail_jumps);
+
+                for (index, element) in elements.iter().enumerate() {
+                    path.push(PathStep::Index(index));
+                    self.pattern_test(slot, path, element, fail_jumps);
+                    path.pop();
+                }
+            }
+            Pattern::Variant {
+                enum_name,
+                variant,
+                values,
+            } => {
+                let empty = Vec::new();
+                let patterns = values.as_ref().unwrap_or(&empty);
+                if patterns.len() > u8::MAX as usize {
+                    self.error("Can't have more than 255 values in a variant pattern.");
+                }
+                self.emit_path(slot, path);
+                self.named_variable(enum_name.clone(), false);
+                let name_constant = self.identifier_constant(variant.clone());
+                self.emit_op_byte(OpCode::GetProperty, name_constant);
+                self.emit_op_byte(OpCode::MatchVariant, patterns.len() as u8);
+                self.emit_byte(values.is_some() as u8);
+                self.emit_test_jump(fail_jumps);
+
+                for (index, value) in patterns.iter().enumerate() {
+                    path.push(PathStep::VariantValue(index));
+                    self.pattern_test(slot, path, value, fail_jumps);
+                    path.pop();
+                }
+            }
+        }
+    }
+
+    fn emit_test_jump(&mut self, fail_jumps: &mut Vec<usize>) {
+        fail_jumps.push(self.emit_jump(OpCode::JumpIfFalse));
+        self.emit_op(OpCode::Pop);
+    }
+
+    /// Pushes every name pattern binds as a local, once the whole pattern is known to match
+    fn pattern_bindings(&mut self, slot: u8, path: &mut Vec<PathStep>, pattern: &Pattern) {
+        match pattern {
+            Pattern::Wildcard | Pattern::Literal(_) | Pattern::Range { .. } => {}
+            Pattern::Binding(name) => self.bind_path(slot, path, name.clone()),
+            Pattern::Instance { fields, .. } => {
+                for (field, pattern) in fields {
+                    path.push(PathStep::Field(field.clone()));
+                    self.pattern_bindings(slot, path, pattern);
+                    path.pop();
+                }
+            }
+            Pattern::List { elements, rest } => {
+                for (index, element) in elements.iter().enumerate() {
+                    path.push(PathStep::Index(index));
+                    self.pattern_bindings(slot, path, element);
+                    path.pop();
+                }
+                if let Some(Some(name)) = rest {
+                    path.push(PathStep::From(elements.len()));
+                    self.bind_path(slot, path, name.clone());
+                    path.pop();
+                }
+            }
+            Pattern::Variant { values, .. } => {
+                for (index, value) in values.iter().flatten().enumerate() {
+                    path.push(PathStep::VariantValue(index));
+                    self.pattern_bindings(slot, path, value);
+                    path.pop();
+                }
+            }
+        }
+    }
+
+    fn bind_path(&mut self, slot: u8, path: &[PathStep], name: Rc<str>) {
+        self.emit_path(slot, path);
+        self.declare_variable(name);
+        self.mark_initialized();
+    }
+
+    fn emit_path(&mut self, slot: u8, path: &[PathStep]) {
+        self.emit_op_byte(OpCode::GetLocal, slot);
+        for step in path {
+            match step {
+                PathStep::Field(name) => {
+                    let name_constant = self.identifier_constant(name.clone());
+                    self.emit_op_byte(OpCode::GetProperty, name_constant);
+                }
+                PathStep::Index(index) => {
+                    self.emit_constant(Value::Number(*index as f64));
+                    self.emit_op(OpCode::GetIndex);
+                }
+                PathStep::From(index) => {
+                    self.emit_constant(Value::Number(*index as f64));
+                    let slice = self.identifier_constant(Rc::from("slice"));
+                    self.emit_op_byte(OpCode::Invoke, slice);
+                    self.emit_byte(1);
+                }
+                PathStep::VariantValue(index) => {
+                    self.emit_op_byte(OpCode::GetVariantValue, *index as u8);
+                }
+            }
+        }
+    }
+
+    fn check_reachable(
+        &mut self,
+        pattern_start: usize,
+        pattern: &Pattern,
+        has_guard: bool,
+        coverage: &mut Coverage,
+    ) {
+        let is_covered = coverage.catch_all
+            || match pattern {
+                Pattern::Literal(value) => coverage.literals.contains(value),
+                Pattern::Variant { variant, .. } => coverage.variants.contains(variant),
+                _ => false,
+            };
+        if is_covered {
+            self.warning_at(pattern_start, "Unreachable match arm.");
+        }
+
+        let set = match pattern {
+            Pattern::Literal(Value::Bool(_)) => ClosedSet::Booleans,
+            Pattern::Variant { enum_name, .. } => ClosedSet::Variants(enum_name.clone()),
+            _ => ClosedSet::Open,
+        };
+        if coverage.set == ClosedSet::NoArms || coverage.set == set {
+            coverage.set = set;
+        } else {
+            coverage.set = ClosedSet::Open;
+        }
+
+        if has_guard {
+            return;
+        }
+        match pattern {
+            pattern if pattern.is_catch_all() => coverage.catch_all = true,
+            Pattern::Literal(value) => coverage.literals.push(value.clone()),
+            Pattern::Variant {
+                variant, values, ..
+            } => {
+                let matches_any_values = values.iter().flatten().all(|value| value.is_catch_all());
+                if matches_any_values {
+                    coverage.variants.push(variant.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // booleans and the variants of an enum declared in this file are the closed sets, any
+    // other match can need a catch all arm
+    fn warn_missing_cases(&mut self, keyword: usize, coverage: &Coverage) {
+        if coverage.catch_all {
+            return;
+        }
+
+        let missing: Vec<String> = match &coverage.set {
+            ClosedSet::Booleans => [true, false]
+                .iter()
+                .filter(|value| !coverage.literals.contains(&Value::Bool(**value)))
+                .map(|value| value.to_string())
+                .collect(),
+            ClosedSet::Variants(enum_name) => match self.enums.get(enum_name) {
+                Some(variants) => variants
+                    .iter()
+                    .filter(|variant| !coverage.variants.contains(variant))
+                    .map(|variant| format!("{}.{}", enum_name, variant))
+                    .collect(),
+                None => Vec::new(),
+            },
+            ClosedSet::NoArms | ClosedSet::Open => Vec::new(),
+        };
+
+        for case in missing {
+            let message = format!("Match has no arm for {}.", case);
+            self.warning_at(keyword, &message);
+        }
+    }
+}
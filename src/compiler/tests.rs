@@ -0,0 +1,1243 @@
+use super::*;
+use crate::chunk::disassembler::disassemble_chunk;
+use crate::vm::error::InterpretError;
+use crate::vm::Vm;
+
+fn run(source: &str) -> (Result<(), InterpretError>, String) {
+    let mut output = Vec::new();
+    let result = Vm::new(&mut output).interpret(String::from(source));
+    (result, String::from_utf8(output).unwrap())
+}
+
+fn assert_prints(source: &str, expected: &str) {
+    let (result, output) = run(source);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, expected);
+}
+
+fn syntax_errors(source: &str) -> Vec<String> {
+    match compile(String::from(source)) {
+        Err(CompileError::Syntax(errors)) => errors.iter().map(|e| e.to_string()).collect(),
+        other => panic!("expected syntax errors, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn compiles_expression_statement() {
+    let script = compile(String::from("print 1 + 2 * 3;")).unwrap();
+
+    assert_eq!(
+        disassemble_chunk(&script.chunk, "script"),
+        "== script ==\n\
+         0000    1 OP_CONSTANT         0 '1'\n\
+         0002    | OP_CONSTANT         1 '2'\n\
+         0004    | OP_CONSTANT         2 '3'\n\
+         0006    | OP_MULTIPLY\n\
+         0007    | OP_ADD\n\
+         0008    | OP_PRINT\n\
+         0009    | OP_NIL\n\
+         0010    | OP_RETURN\n"
+    );
+}
+
+#[test]
+fn precedence_and_grouping() {
+    assert_prints(
+        "print -(1 + 2) * 3 - 4 / 2; print !(1 >= 2) == true; print 1 <= 1 and 2 != 2;",
+        "-11\ntrue\nfalse\n",
+    );
+}
+
+#[test]
+fn strings_and_globals() {
+    assert_prints(
+        r#"var greeting = "hello"; greeting = greeting + " world"; print greeting;"#,
+        "hello world\n",
+    );
+}
+
+#[test]
+fn block_scoped_locals_shadow() {
+    assert_prints(
+        "var a = \"global\";
+        {
+            var a = \"outer\";
+            {
+                var a = \"inner\";
+                print a;
+            }
+            print a;
+        }
+        print a;",
+        "inner\nouter\nglobal\n",
+    );
+}
+
+#[test]
+fn logical_operators_short_circuit() {
+    assert_prints(
+        "print nil or \"default\"; print false and undefined; print 1 and 2;",
+        "default\nfalse\n2\n",
+    );
+}
+
+#[test]
+fn if_while_and_for_loops() {
+    assert_prints(
+        "if (1 > 2) print \"then\"; else print \"else\";
+        var i = 0;
+        while (i < 3) i = i + 1;
+        print i;
+        for (var j = 0; j < 3; j = j + 1) print j;",
+        "else\n3\n0\n1\n2\n",
+    );
+}
+
+#[test]
+fn recursive_function() {
+    assert_prints(
+        "fun fib(n) {
+            if (n < 2) return n;
+            return fib(n - 2) + fib(n - 1);
+        }
+        print fib(15);
+        print fib;",
+        "610\n<fn fib>\n",
+    );
+}
+
+#[test]
+fn classes_methods_and_initializers() {
+    assert_prints(
+        "class Counter {
+            init(start) { this.count = start; }
+            increment() { this.count = this.count + 1; return this; }
+        }
+        var counter = Counter(10);
+        counter.increment().increment();
+        print counter.count;
+        print counter;
+        print Counter;
+        print counter.init(1).count;",
+        "12\nCounter instance\nCounter\n1\n",
+    );
+}
+
+#[test]
+fn inherited_methods_and_callable_fields() {
+    assert_prints(
+        "class Animal { speak() { return \"...\"; } }
+        class Dog < Animal {}
+        fun bark() { return \"woof\"; }
+        var dog = Dog();
+        print dog.speak();
+        dog.speak = bark;
+        print dog.speak();",
+        "...\nwoof\n",
+    );
+}
+
+#[test]
+fn compiles_closure_capturing_local() {
+    let script = compile(String::from(
+        "fun outer() { var x = 1; fun inner() { return x; } return inner; }",
+    ))
+    .unwrap();
+    let outer = match &script.chunk.constants[1] {
+        Value::Function(function) => function.clone(),
+        other => panic!("expected a function, got {}", other),
+    };
+
+    assert_eq!(
+        disassemble_chunk(&outer.chunk, "outer"),
+        "== outer ==\n\
+         0000    1 OP_CONSTANT         0 '1'\n\
+         0002    | OP_CLOSURE          1 <fn inner>\n\
+         0004    |                     local 1\n\
+         0006    | OP_GET_LOCAL        2\n\
+         0008    | OP_RETURN\n\
+         0009    | OP_NIL\n\
+         0010    | OP_RETURN\n"
+    );
+}
+
+#[test]
+fn compiles_default_parameter_and_named_call() {
+    let script = compile(String::from("fun f(a = 1) {} f(a: 2);")).unwrap();
+    let f = match &script.chunk.constants[1] {
+        Value::Function(function) => function.clone(),
+        other => panic!("expected a function, got {}", other),
+    };
+
+    assert_eq!(
+        disassemble_chunk(&f.chunk, "f"),
+        "== f ==\n\
+         0000    1 OP_JUMP_IF_PASSED    0 -> 9 (parameter 0)\n\
+         0004    | OP_CONSTANT         0 '1'\n\
+         0006    | OP_SET_LOCAL        1\n\
+         0008    | OP_POP\n\
+         0009    | OP_NIL\n\
+         0010    | OP_RETURN\n"
+    );
+    assert!(disassemble_chunk(&script.chunk, "script")
+        .contains("0010    | OP_CALL_NAMED       0 + 1 named\n"));
+}
+
+#[test]
+fn compiles_for_in_loop() {
+    let script = compile(String::from("for (var x in 0..2) print x;")).unwrap();
+
+    assert_eq!(
+        disassemble_chunk(&script.chunk, "script"),
+        "== script ==\n\
+         0000    1 OP_CONSTANT         0 '0'\n\
+         0002    | OP_CONSTANT         1 '2'\n\
+         0004    | OP_BUILD_RANGE      0\n\
+         0006    | OP_GET_ITER\n\
+         0007    | OP_ITER_NEXT        7 -> 21 (slot 1)\n\
+         0011    | OP_ITER_CHECK      11 -> 21\n\
+         0014    | OP_GET_LOCAL        2\n\
+         0016    | OP_PRINT\n\
+         0017    | OP_POP\n\
+         0018    | OP_LOOP            18 -> 7\n\
+         0021    | OP_POP\n\
+         0022    | OP_NIL\n\
+         0023    | OP_RETURN\n"
+    );
+}
+
+#[test]
+fn closure_outlives_its_frame() {
+    assert_prints(
+        "fun makeCounter() {
+            var count = 0;
+            fun increment() { count = count + 1; print count; }
+            return increment;
+        }
+        var first = makeCounter();
+        first();
+        first();
+        var second = makeCounter();
+        second();
+        first();",
+        "1\n2\n1\n3\n",
+    );
+}
+
+#[test]
+fn sibling_closures_share_upvalue() {
+    assert_prints(
+        "var get; var set;
+        fun make() {
+            var value = \"before\";
+            fun getter() { print value; }
+            fun setter() { value = \"after\"; }
+            get = getter;
+            set = setter;
+            getter();
+        }
+        make();
+        set();
+        get();",
+        "before\nafter\n",
+    );
+}
+
+#[test]
+fn upvalue_sees_assignment_while_open() {
+    assert_prints(
+        "{
+            var a = 1;
+            fun show() { print a; }
+            a = 2;
+            show();
+        }",
+        "2\n",
+    );
+}
+
+#[test]
+fn nested_closures_capture_through_middle_function() {
+    assert_prints(
+        "fun outer() {
+            var x = \"outside\";
+            fun middle() {
+                fun inner() { print x; }
+                return inner;
+            }
+            return middle;
+        }
+        outer()()();",
+        "outside\n",
+    );
+}
+
+#[test]
+fn closures_in_loops() {
+    // each iteration's block local is a fresh variable, the loop variable is shared
+    assert_prints(
+        "var a; var b; var c; var d;
+        for (var i = 0; i < 2; i = i + 1) {
+            var j = i;
+            fun byBlock() { print j; }
+            fun byLoop() { print i; }
+            if (a == nil) { a = byBlock; c = byLoop; } else { b = byBlock; d = byLoop; }
+        }
+        a(); b(); c(); d();",
+        "0\n1\n2\n2\n",
+    );
+}
+
+#[test]
+fn closed_block_local() {
+    assert_prints(
+        "var f;
+        {
+            var local = \"block\";
+            fun g() { print local; }
+            f = g;
+        }
+        f();",
+        "block\n",
+    );
+}
+
+#[test]
+fn methods_close_over_this_and_super() {
+    assert_prints(
+        "class A { method() { print \"A method\"; } }
+        class B < A {
+            method() { print \"B method\"; }
+            test() { super.method(); }
+            later() {
+                fun call() { this.method(); var m = super.method; m(); }
+                return call;
+            }
+        }
+        class C < B {}
+        C().test();
+        C().later()();",
+        "A method\nB method\nA method\n",
+    );
+}
+
+#[test]
+fn list_literals_and_indexing() {
+    assert_prints(
+        "var l = [1, \"two\", [3]]; l[0] = l[0] + 10; l.push(nil); \
+         print l; print l[2][0]; print l.len(); print l.pop(); print [];",
+        "[11, \"two\", [3], nil]\n3\n4\nnil\n[]\n",
+    );
+}
+
+#[test]
+fn map_literals_keys_and_methods() {
+    assert_prints(
+        "class K {} var k = K(); \
+         var m = {\"a\": 1, 2: \"b\", true: nil, nil: 0, k: k}; \
+         m[\"a\"] = m[\"a\"] + 1; m[-0] = \"zero\"; \
+         print m[\"a\"]; print m[0]; print m[k] == k; print m.len(); \
+         print m.has(2); print m.remove(2); print m.has(2); print m.remove(2); \
+         print m.keys(); print len(m.values()); print {};",
+        "2\nzero\ntrue\n6\ntrue\nb\nfalse\nnil\n[\"a\", true, nil, K instance, -0]\n5\n{}\n",
+    );
+}
+
+#[test]
+fn brace_starts_a_block_in_statement_position() {
+    assert_prints("{ var a = {1: 2}; print a; }", "{1: 2}\n");
+}
+
+#[test]
+fn collections_print_cycles() {
+    assert_prints(
+        "var l = [1]; l.push(l); print l; var m = {}; m[\"self\"] = m; print m;",
+        "[1, [...]]\n{\"self\": {...}}\n",
+    );
+}
+
+#[test]
+fn collection_runtime_errors() {
+    let errors = [
+        ("[1][1];", "List index out of range."),
+        ("[1][0.5];", "List index must be a non-negative integer."),
+        ("({})[\"x\"];", "Undefined key \"x\"."),
+        (
+            "({})[clock] = 1;",
+            "Map keys must be strings, numbers, booleans, nil or instances.",
+        ),
+        ("var m = {0/0: 1};", "Map keys can't be NaN."),
+        ("1[0];", "Only lists, maps and strings can be indexed."),
+        (r#""abc"[3];"#, "String index out of range."),
+        (r#""abc"[0] = "x";"#, "Strings can't be changed by index."),
+        ("[].pop();", "Can't pop from an empty list."),
+        ("[].push();", "Expected 1 arguments but got 0."),
+        ("[].nope();", "Undefined property 'nope'."),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            run(source).0,
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn anonymous_functions_are_closures() {
+    assert_prints(
+        "fun apply(f, x) { return f(x); } \
+         var n = 10; \
+         print apply(fun (x) { return x + n; }, 1); \
+         print apply((x) => x * n, 2); \
+         var add = (a, b) => a + b; print add(3, 4); \
+         var nothing = () => nil; print nothing(); \
+         print fun () {}; \
+         fun () { print \"statement\"; }(); \
+         print (1 + 2) * 3;",
+        "11\n20\n7\nnil\n<fn anonymous>\nstatement\n9\n",
+    );
+}
+
+#[test]
+fn default_rest_and_named_parameters() {
+    assert_prints(
+        "fun f(a, b = a * 2, c = \"c\") { print a; print b; print c; } \
+         f(1); f(1, 5); f(c: 3, a: 4); f(1, c: nil); \
+         fun g(first, ...rest) { print rest; } g(1); g(1, 2, 3); \
+         class P { init(x, y = 0) { this.x = x; this.y = y; } \
+           sum(extra = 0) { return this.x + this.y + extra; } } \
+         var p = P(y: 2, x: 1); print p.sum(); print p.sum(extra: 10); \
+         var area = (w, h = w) => w * h; print area(3);",
+        "1\n2\nc\n1\n5\nc\n4\n8\n3\n1\n2\nnil\n[]\n[2, 3]\n3\n13\n9\n",
+    );
+}
+
+#[test]
+fn parameter_runtime_errors() {
+    let errors = [
+        (
+            "fun f(a, b = 1) {} f();",
+            "Expected 1 to 2 arguments but got 0.",
+        ),
+        (
+            "fun f(a, b = 1) {} f(1, 2, 3);",
+            "Expected 1 to 2 arguments but got 3.",
+        ),
+        (
+            "fun f(a, ...r) {} f();",
+            "Expected at least 1 arguments but got 0.",
+        ),
+        ("fun f(a) {} f(b: 1);", "Unexpected argument named 'b'."),
+        (
+            "fun f(a) {} f(1, a: 1);",
+            "Argument 'a' was given more than once.",
+        ),
+        ("fun f(a, b) {} f(b: 1);", "Missing argument 'a'."),
+        (
+            "fun f(a, ...r) {} f(r: 1);",
+            "Unexpected argument named 'r'.",
+        ),
+        (
+            "clock(a: 1);",
+            "Native functions don't take named arguments.",
+        ),
+        ("class A {} A(a: 1);", "Expected 0 arguments but got 1."),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            run(source).0,
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn try_catch_and_throw() {
+    assert_prints(
+        "try { throw \"oops\"; print \"unreachable\"; } catch (e) { print e; } \
+         fun fail() { return 1 + nil; } \
+         try { fail(); } catch (e) { print e.message; print e.line; print e.stack; } \
+         try { undefined; } catch (e) { print e.message; } \
+         class Custom < Error {} \
+         try { throw Custom(\"custom\"); } catch (e) { print e.message; print e.line; } \
+         try { try { throw 1; } catch (e) { throw e + 1; } } catch (e) { print e; } \
+         print \"after\";",
+        "oops\nOperands must be two numbers or two strings.\n1\n[\"[line 1] in fail()\", \"[line 1] in script\"]\n\
+         Undefined variable 'undefined'.\ncustom\n1\n2\nafter\n",
+    );
+}
+
+#[test]
+fn finally_runs_on_every_exit() {
+    assert_prints(
+        "fun f(x) { \
+           var local = \"kept\"; \
+           try { if (x == 1) return \"returned\"; if (x == 2) throw \"thrown\"; } \
+           finally { print \"finally \" + local; } \
+           return \"fell through\"; } \
+         print f(0); print f(1); \
+         try { f(2); } catch (e) { print e; } \
+         fun g() { try { try { return 1; } finally { print \"inner\"; } } finally { print \"outer\"; } } \
+         print g(); \
+         fun h() { try { return \"try\"; } finally { return \"finally\"; } } print h(); \
+         try { throw 1; } catch (e) { print \"caught\"; } finally { print \"done\"; } \
+         fun k() { for (var i = 0; i < 3; i = i + 1) { var c = fun () { return i; }; \
+           try { if (i == 1) return c; } catch (e) {} } } print k()();",
+        "finally kept\nfell through\nfinally kept\nreturned\nfinally kept\nthrown\n\
+         inner\nouter\n1\nfinally\ncaught\ndone\n1\n",
+    );
+}
+
+#[test]
+fn uncaught_throw_is_a_runtime_error() {
+    let (result, output) = run("try { print 1; } finally { print 2; }\nthrow \"bad\";");
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "Uncaught exception \"bad\"."
+        )))
+    );
+    assert_eq!(output, "1\n2\n");
+    assert_eq!(
+        run("fun f() { try { throw Error(\"inner\"); } finally {} } f();").0,
+        Err(InterpretError::RuntimeError(String::from("inner")))
+    );
+}
+
+#[test]
+fn runtime_error_reports_message() {
+    let (result, output) = run("print 1;\nprint \"a\" - 1;");
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "Operands must be numbers."
+        )))
+    );
+    assert_eq!(output, "1\n");
+}
+
+#[test]
+fn runtime_error_closes_upvalues() {
+    // a repl session keeps using the vm, and the closures that escaped, after an error
+    let mut output = Vec::new();
+    let mut vm = Vm::new(&mut output);
+    let escape = vm.interpret(String::from(
+        "var get; var set;
+        {
+            var a; var b; var c;
+            var x = 1;
+            fun getter() { print x; }
+            fun setter() { x = 2; }
+            get = getter;
+            set = setter;
+            nil();
+        }",
+    ));
+    let after = vm.interpret(String::from("get(); set(); get();"));
+
+    assert_eq!(
+        escape,
+        Err(InterpretError::RuntimeError(String::from(
+            "Can only call functions and classes."
+        )))
+    );
+    assert_eq!(after, Ok(()));
+    assert_eq!(String::from_utf8(output).unwrap(), "1\n2\n");
+}
+
+#[test]
+fn error_expect_expression() {
+    assert_eq!(
+        syntax_errors("print 1 +;"),
+        vec!["[line 1] Error at ';': Expect expression."]
+    );
+}
+
+#[test]
+fn error_at_end() {
+    assert_eq!(
+        syntax_errors("print 1"),
+        vec!["[line 1] Error at end: Expect ';' after value."]
+    );
+}
+
+#[test]
+fn error_reports_source_text_of_token() {
+    assert_eq!(
+        syntax_errors("print 1 2.50;\nprint 1 007;"),
+        vec![
+            "[line 1] Error at '2.50': Expect ';' after value.",
+            "[line 2] Error at '007': Expect ';' after value.",
+        ]
+    );
+}
+
+#[test]
+fn error_invalid_assignment_target() {
+    assert_eq!(
+        syntax_errors("var a; var b;\na + b = 1;"),
+        vec!["[line 2] Error at '=': Invalid assignment target."]
+    );
+}
+
+#[test]
+fn error_local_in_own_initializer() {
+    assert_eq!(
+        syntax_errors("{ var a = a; }"),
+        vec!["[line 1] Error at 'a': Can't read local variable in its own initializer."]
+    );
+}
+
+#[test]
+fn error_redeclared_local() {
+    assert_eq!(
+        syntax_errors("{ var a = 1; var a = 2; }"),
+        vec!["[line 1] Error at 'a': Already a variable with this name in this scope."]
+    );
+}
+
+#[test]
+fn error_misplaced_return_and_this() {
+    assert_eq!(
+        syntax_errors("return 1;\nprint this;\nclass A { init() { return 1; } }"),
+        vec![
+            "[line 1] Error at 'return': Can't return from top-level code.",
+            "[line 2] Error at 'this': Can't use 'this' outside of a class.",
+            "[line 3] Error at 'return': Can't return a value from an initializer.",
+        ]
+    );
+}
+
+#[test]
+fn error_class_inherits_from_itself() {
+    assert_eq!(
+        syntax_errors("class A < A {}"),
+        vec!["[line 1] Error at 'A': A class can't inherit from itself."]
+    );
+}
+
+#[test]
+fn error_misplaced_super() {
+    assert_eq!(
+        syntax_errors(
+            "print super.x;\nclass A { f() { super.f(); } }\nclass B < A { g() { super; } }"
+        ),
+        vec![
+            "[line 1] Error at 'super': Can't use 'super' outside of a class.",
+            "[line 2] Error at 'super': Can't use 'super' in a class with no superclass.",
+            "[line 3] Error at ';': Expect '.' after 'super'.",
+        ]
+    );
+}
+
+#[test]
+fn synchronizes_after_error() {
+    assert_eq!(
+        syntax_errors("var = 1;\nprint 2\nvar b = 3;\nfun f( {}"),
+        vec![
+            "[line 1] Error at '=': Expect variable name.",
+            "[line 3] Error at 'var': Expect ';' after value.",
+            "[line 4] Error at '{': Expect parameter name.",
+        ]
+    );
+}
+
+#[test]
+fn error_malformed_collection_literals() {
+    assert_eq!(
+        syntax_errors("print [1, 2;\nprint {1 2};\nprint {1: 2;"),
+        vec![
+            "[line 1] Error at ';': Expect ']' after list elements.",
+            "[line 2] Error at '2': Expect ':' after map key.",
+            "[line 3] Error at ';': Expect '}' after map entries.",
+        ]
+    );
+}
+
+#[test]
+fn error_malformed_anonymous_functions() {
+    assert_eq!(
+        syntax_errors("var f = fun x() {};")[0],
+        "[line 1] Error at 'x': Expect '(' after 'fun'."
+    );
+    assert_eq!(
+        syntax_errors("var g = (a, 1) => a;"),
+        vec!["[line 1] Error at '1': Expect parameter name."]
+    );
+    assert_eq!(
+        syntax_errors("fun;")[0],
+        "[line 1] Error at ';': Expect function name."
+    );
+}
+
+#[test]
+fn error_malformed_parameters() {
+    assert_eq!(
+        syntax_errors("fun f(a = 1, b) {}"),
+        vec![
+            "[line 1] Error at 'b': A parameter without a default can't follow one with a default."
+        ]
+    );
+    assert_eq!(
+        syntax_errors("fun f(...a, b) {}")[0],
+        "[line 1] Error at ',': A rest parameter must be the last parameter."
+    );
+    assert_eq!(
+        syntax_errors("fun f(a, b = b) {}"),
+        vec!["[line 1] Error at 'b': Can't read local variable in its own initializer."]
+    );
+    assert_eq!(
+        syntax_errors("f(a: 1, 2);"),
+        vec!["[line 1] Error at '2': Positional arguments can't follow named ones."]
+    );
+}
+
+#[test]
+fn error_malformed_try() {
+    assert_eq!(
+        syntax_errors("try { print 1; }"),
+        vec!["[line 1] Error at 'try': Expect 'catch' or 'finally' after try block."]
+    );
+    assert_eq!(
+        syntax_errors("try {} catch e {}")[0],
+        "[line 1] Error at 'e': Expect '(' after 'catch'."
+    );
+}
+
+#[test]
+fn error_malformed_imports() {
+    assert_eq!(
+        syntax_errors("import math as m;"),
+        vec!["[line 1] Error at 'math': Expect module path after 'import'."]
+    );
+    assert_eq!(
+        syntax_errors("import \"math.lox\" m;"),
+        vec!["[line 1] Error at 'm': Expect 'as' after module path."]
+    );
+    assert_eq!(
+        syntax_errors("from \"math.lox\" sqrt;"),
+        vec!["[line 1] Error at 'sqrt': Expect 'import' after module path."]
+    );
+}
+
+#[test]
+fn from_and_as_are_still_names() {
+    assert_prints("var from = 1; var as = 2; print from + as;", "3\n");
+}
+
+#[test]
+fn match_statement_patterns() {
+    assert_prints(
+        "class Point { init(x, y) { this.x = x; this.y = y; } } \
+         class Point3 < Point { init(x, y, z) { super.init(x, y); this.z = z; } } \
+         fun describe(value) { \
+           match (value) { \
+             0 => print \"zero\"; \
+             -1 => print \"minus one\"; \
+             1..10 => print \"small\"; \
+             10..=20 => print \"medium\"; \
+             \"hi\" => print \"greeting\"; \
+             nil => print \"nothing\"; \
+             true => print \"yes\"; \
+             Point { x: 0, y } => print \"on the y axis at \" + y; \
+             Point { x, y } if x == y => { print \"diagonal\"; print x; } \
+             Point { x } => print x; \
+             [] => print \"empty\"; \
+             [first, ...rest] if rest.len() > 1 => print rest; \
+             [first, ...] => print first; \
+             n => print \"other \" + n; \
+           } } \
+         describe(0); describe(-1); describe(5); describe(10); describe(20); describe(\"hi\"); \
+         describe(nil); describe(true); describe(Point(0, \"up\")); describe(Point(2, 2)); \
+         describe(Point3(3, 4, 5)); describe([]); describe([1, 2, 3]); describe([1, 2]); \
+         describe(\"other\"); \
+         match (false) { true => print \"never\"; }",
+        "zero\nminus one\nsmall\nmedium\nmedium\ngreeting\nnothing\nyes\non the y axis at up\n\
+         diagonal\n2\n3\nempty\n[2, 3]\n1\nother other\n",
+    );
+}
+
+#[test]
+fn match_expressions() {
+    assert_prints(
+        "fun sign(n) { return match (n) { 0 => \"zero\", -1000..0 => \"negative\", _ => \"positive\" }; } \
+         print sign(0) + \" \" + sign(-3) + \" \" + sign(7); \
+         var offset = 10; \
+         var shifted = match ([1, 2]) { [a, b] if a > b => a, [a, b] => fun () { return offset + b; } }; \
+         print shifted(); \
+         print 1 + match (2) { 2 => 3 } * 2;",
+        "zero negative positive\n12\n7\n",
+    );
+    assert_eq!(
+        run("print match (3) { 1 => \"one\" };").0,
+        Err(InterpretError::RuntimeError(String::from(
+            "No match arm for 3."
+        )))
+    );
+    assert_eq!(
+        run("match (1) { Missing { x } => print x; }").0,
+        Err(InterpretError::RuntimeError(String::from(
+            "Undefined variable 'Missing'."
+        )))
+    );
+}
+
+#[test]
+fn match_warnings() {
+    let warnings = |source: &str| -> Vec<String> {
+        let (_, warnings) = compile_with_warnings(String::from(source)).unwrap();
+        warnings.iter().map(|w| w.to_string()).collect()
+    };
+
+    assert_eq!(
+        warnings("match (1) {\n _ => print 1;\n 2 => print 2;\n}"),
+        vec!["[line 3] Warning at '2': Unreachable match arm."]
+    );
+    assert_eq!(
+        warnings("match (1) { 1 => print 1; 1 => print 2; n if n > 0 => print n; 3 => print 3; }"),
+        vec!["[line 1] Warning at '1': Unreachable match arm."]
+    );
+    assert_eq!(
+        warnings("print match (true) { true => 1 };"),
+        vec!["[line 1] Warning at 'match': Match has no arm for false."]
+    );
+    assert!(warnings("match (true) { true => print 1; false => print 2; }").is_empty());
+}
+
+#[test]
+fn error_malformed_match() {
+    assert_eq!(
+        syntax_errors("match (1) { 1 print 1; }")[0],
+        "[line 1] Error at 'print': Expect '=>' after match pattern."
+    );
+    assert_eq!(
+        syntax_errors("match 1 { }")[0],
+        "[line 1] Error at '1': Expect '(' after 'match'."
+    );
+    assert_eq!(
+        syntax_errors("print match (1) { + => 1 };")[0],
+        "[line 1] Error at '+': Expect pattern."
+    );
+    assert_eq!(
+        syntax_errors("match (1) { [a, ...b, c] => print a; }")[0],
+        "[line 1] Error at ',': Expect ']' after list pattern."
+    );
+}
+
+#[test]
+fn operator_overloading() {
+    assert_prints(
+        "class Vec { init(x, y) { this.x = x; this.y = y; } \
+           __add__(o) { return Vec(this.x + o.x, this.y + o.y); } \
+           __sub__(o) { return Vec(this.x - o.x, this.y - o.y); } \
+           __mul__(k) { return Vec(this.x * k, this.y * k); } \
+           __neg__() { return Vec(-this.x, -this.y); } \
+           __eq__(o) { return this.x == o.x and this.y == o.y; } \
+           __index__(i) { if (i == 0) return this.x; return this.y; } \
+           __str__() { return \"(\" + to_string(this.x) + \", \" + to_string(this.y) + \")\"; } } \
+         var a = Vec(1, 2); var b = Vec(3, 4); \
+         print a + b; print b - a; print a * 3; print -a; \
+         print a == Vec(1, 2); print a != b; print b[1]; \
+         class Money { init(cents) { this.cents = cents; } \
+           __lt__(o) { return this.cents < o.cents; } \
+           toString() { return \"$\" + to_string(this.cents / 100); } } \
+         print Money(150); print Money(1) < Money(2); print Money(1) > Money(2); \
+         print Money(3) >= Money(2);",
+        "(4, 6)\n(2, 2)\n(3, 6)\n(-1, -2)\ntrue\ntrue\n4\n$1.5\ntrue\nfalse\ntrue\n",
+    );
+
+    let errors = [
+        (
+            "class A {} A() + A();",
+            "Operands must be two numbers or two strings.",
+        ),
+        ("class A {} -A();", "Operand must be a number."),
+        (
+            "class A {} A()[0];",
+            "Only lists, maps and strings can be indexed.",
+        ),
+        (
+            "class A { __add__() { return 1; } } A() + 1;",
+            "Expected 0 arguments but got 1.",
+        ),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            run(source).0,
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        )
+    }
+}
+
+#[test]
+fn class_methods_and_getters() {
+    assert_prints(
+        "class Math { class square(n) { return n * n; } class twice(n) { return this.square(n) * 2; } } \
+         print Math.square(3); print Math.twice(2); var square = Math.square; print square(4); \
+         class Rect { init(w, h) { this.w = w; this.h = h; } area { return this.w * this.h; } \
+           describe() { return this.area + 1; } } \
+         var r = Rect(2, 3); print r.area; r.w = 10; print r.describe(); \
+         class Square < Rect { init(side) { super.init(side, side); } \
+           area { print \"square\"; return super.area; } class unit() { return Square(1); } } \
+         print Square(4).area; print Square.unit().area;",
+        "9\n8\n16\n6\n31\nsquare\n16\nsquare\n1\n",
+    );
+
+    let errors = [
+        ("class A {} A.missing;", "Undefined property 'missing'."),
+        ("class A {} A.missing();", "Undefined property 'missing'."),
+        (
+            "class A { size { return 1; } } A().size();",
+            "Getter 'size' is not called with arguments.",
+        ),
+        (
+            "class A { class make() { return 1; } } A().make();",
+            "Undefined property 'make'.",
+        ),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            run(source).0,
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        );
+    }
+    assert_eq!(
+        syntax_errors("class A { init { } }"),
+        vec!["[line 1] Error at 'init': An initializer must have a parameter list."]
+    );
+}
+
+#[test]
+fn traits_mix_methods_into_classes() {
+    assert_prints(
+        "class Animal { init(name) { this.name = name; } speak() { return \"...\"; } } \
+         trait Walkable { walk() { return this.name + \" walks\"; } speak() { return \"step\"; } } \
+         trait Barkable { bark() { return this.name + \" barks\"; } speak() { return \"woof\"; } \
+           loud { return this.bark() + \"!\"; } } \
+         class Dog < Animal with Walkable, Barkable { speak() { return \"dog \" + super.speak(); } } \
+         var dog = Dog(\"rex\"); print dog.walk(); print dog.bark(); print dog.loud; print dog.speak(); \
+         class Robot with Walkable { init() { this.name = \"bot\"; } } \
+         print Robot().walk(); print Robot().speak(); print Walkable; print type(Walkable);",
+        "rex walks\nrex barks\nrex barks!\ndog ...\nbot walks\nstep\n<trait Walkable>\ntrait\n",
+    );
+
+    let errors = [
+        (
+            "class A {} class B with A {}",
+            "Only traits can follow 'with'.",
+        ),
+        ("trait T {} T();", "Can only call functions and classes."),
+        ("trait T {} class A < T {}", "Superclass must be a class."),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            run(source).0,
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn error_conflicting_traits() {
+    assert_eq!(
+        syntax_errors("trait A { f() {} g() {} } trait B { f() {} }\nclass C with A, B {}"),
+        vec!["[line 2] Error at 'B': Traits 'A' and 'B' both define 'f', the class must override it."]
+    );
+    assert!(compile(String::from(
+        "trait A { f() {} } trait B { f() {} } class C with A, B { f() {} }"
+    ))
+    .is_ok());
+    assert_eq!(
+        syntax_errors("class C with {}")[0],
+        "[line 1] Error at '{': Expect trait name."
+    );
+}
+
+#[test]
+fn for_in_loops() {
+    assert_prints(
+        "for (var x in [1, nil, 3]) print x; \
+         for (var key in {\"a\": 1, \"b\": 2}) print key; \
+         for (var c in \"hé!\") print c; \
+         for (var i in 0..3) print i; \
+         for (var i in 1..=2) print i; \
+         for (var i in 3..0) print \"never\"; \
+         var grown = [1]; for (var x in grown) { if (x < 3) grown.push(x + 1); } print grown; \
+         var closures = []; for (var i in 0..2) closures.push(fun () { return i; }); \
+         print closures[0]() + closures[1](); \
+         class Countdown { init(n) { this.n = n; } iter() { return this; } \
+           next() { if (this.n == 0) return nil; this.n = this.n - 1; return this.n + 1; } } \
+         for (var n in Countdown(3)) print n; \
+         class Bag { init() { this.items = [\"x\", \"y\"]; } iter() { return this.items.iter(); } } \
+         for (var item in Bag()) print item; \
+         var it = [1, 2, 3].iter(); print it.next(); for (var x in it) print x; print it.next(); \
+         print 1..2 + 3; print 0..=1 == 0..=1; print type(0..1);",
+        "1\nnil\n3\na\nb\nh\né\n!\n0\n1\n2\n1\n2\n[1, 2, 3]\n1\n3\n2\n1\nx\ny\n1\n2\n3\nnil\n\
+         1..5\ntrue\nrange\n",
+    );
+
+    let errors = [
+        (
+            "for (var x in 1) print x;",
+            "Only lists, maps, strings, ranges and objects with an iter() method can be iterated.",
+        ),
+        (
+            "for (var x in 1..\"a\") print x;",
+            "Range bounds must be numbers.",
+        ),
+        (
+            "class A {} for (var x in A()) print x;",
+            "Undefined property 'iter'.",
+        ),
+        (
+            "class A { iter() { return 1; } } for (var x in A()) print x;",
+            "Only instances have methods.",
+        ),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            run(source).0,
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        );
+    }
+    assert_eq!(
+        syntax_errors("for (var 1 in x) {}")[0],
+        "[line 1] Error at '1': Expect variable name."
+    );
+}
+
+#[test]
+fn generators_yield_lazily() {
+    assert_prints(
+        "fun count(from, to = from + 2) { \
+           print \"start\"; \
+           for (var i in from..=to) yield i; \
+           return \"ignored\"; } \
+         var numbers = count(1); print type(numbers); print numbers; \
+         print numbers.next(); print numbers.next(); print numbers.next(); print numbers.next(); \
+         print numbers.next(); \
+         fun naturals() { var n = 0; while (true) { yield n; n = n + 1; } } \
+         fun firstThree() { for (var n in naturals()) { if (n == 3) return \"stop\"; print n; } } \
+         print firstThree(); \
+         fun withNil() { yield 1; yield nil; yield 2; } \
+         for (var x in withNil()) print x; \
+         class Tree { init(left, value, right) { this.left = left; this.value = value; this.right = right; } \
+           iter() { if (this.left) for (var x in this.left) yield x; yield this.value; \
+             if (this.right) for (var x in this.right) yield x; } } \
+         for (var x in Tree(Tree(nil, 1, nil), 2, Tree(nil, 3, nil))) print x;",
+        "generator\n<generator count>\nstart\n1\n2\n3\nnil\nnil\n0\n1\n2\nstop\n1\nnil\n2\n1\n2\n3\n",
+    );
+}
+
+#[test]
+fn generators_keep_try_blocks_and_errors() {
+    assert_prints(
+        "fun guarded() { \
+           try { yield 1; yield 2; throw \"inside\"; } catch (e) { yield \"caught \" + e; } \
+           finally { print \"cleanup\"; } } \
+         for (var x in guarded()) print x; \
+         fun failing() { yield 1; throw \"oops\"; } \
+         var g = failing(); print g.next(); \
+         try { g.next(); } catch (e) { print e; } print g.next(); \
+         fun selfish() { yield me.next(); } var me = selfish(); \
+         try { me.next(); } catch (e) { print e.message; }",
+        "1\n2\ncaught inside\ncleanup\n1\noops\nnil\nGenerator is already running.\n",
+    );
+
+    assert_eq!(
+        syntax_errors("yield 1;"),
+        vec!["[line 1] Error at 'yield': Can't yield from top-level code."]
+    );
+    assert_eq!(
+        syntax_errors("class A { init() { yield 1; } }"),
+        vec!["[line 1] Error at 'yield': Can't yield from an initializer."]
+    );
+}
+
+#[test]
+fn constants() {
+    assert_prints(
+        "const PI = 3.14; print PI; \
+         { const local = 1; { var local = 2; local = 3; print local; } print local; } \
+         fun area(r) { const factor = PI; return factor * r * r; } print area(1); \
+         fun shadow() { var PI = 3; PI = 4; return PI; } print shadow();",
+        "3.14\n3\n1\n3.14\n4\n",
+    );
+
+    assert_eq!(
+        syntax_errors("const PI = 3;\nPI = 4;"),
+        vec!["[line 2] Error at 'PI': Can't assign to constant 'PI', declared on line 1."]
+    );
+    assert_eq!(
+        syntax_errors("{ const x = 1;\n fun f() { x = 2; } }"),
+        vec!["[line 2] Error at 'x': Can't assign to constant 'x', declared on line 1."]
+    );
+    assert_eq!(
+        syntax_errors("const x;"),
+        vec!["[line 1] Error at ';': Expect '=' after constant name."]
+    );
+}
+
+#[test]
+fn constants_are_checked_across_repl_lines() {
+    let mut output = Vec::new();
+    let mut vm = Vm::new(&mut output);
+    assert_eq!(vm.interpret(String::from("const PI = 3;")), Ok(()));
+
+    let errors = [
+        ("PI = 4;", "Can't assign to constant 'PI'."),
+        ("var PI = 4;", "Can't redefine constant 'PI'."),
+        ("const PI = 4;", "Can't redefine constant 'PI'."),
+        ("fun PI() {}", "Can't redefine constant 'PI'."),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            vm.interpret(String::from(*source)),
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        );
+    }
+    assert_eq!(vm.interpret(String::from("print PI;")), Ok(()));
+    drop(vm);
+    assert_eq!(String::from_utf8(output).unwrap(), "3\n");
+}
+
+#[test]
+fn enums_with_associated_data() {
+    assert_prints(
+        "enum Shape { Circle(r), Rect(w, h), Empty } \
+         print Shape; print Shape.Circle; print Shape.Empty; \
+         var c = Shape.Circle(2); print c; print c.r; print type(Shape); print type(c); \
+         print Shape.Rect(1, \"a\"); \
+         print c == Shape.Circle(2); print c == Shape.Circle(3); print Shape.Empty == Shape.Empty; \
+         print Shape.Rect(1, 2) == Shape.Circle(1); \
+         var make = Shape.Rect; print make(3, 4).h; \
+         fun area(shape) { \
+           return match (shape) { \
+             Shape.Circle(0) => 0, \
+             Shape.Circle(r) => 3 * r * r, \
+             Shape.Rect(w, h) if w == h => \"square\", \
+             Shape.Rect(w, h) => w * h, \
+             Shape.Empty => nil }; } \
+         print area(Shape.Circle(0)); print area(c); print area(Shape.Rect(2, 2)); \
+         print area(Shape.Rect(2, 3)); print area(Shape.Empty); \
+         match (Shape.Rect(1, 2)) { Shape.Circle => print \"circle\"; Shape.Rect => print \"rect\"; }",
+        "<enum Shape>\n<variant Shape.Circle>\nShape.Empty\nShape.Circle(2)\n2\nenum\nvariant\n\
+         Shape.Rect(1, \"a\")\ntrue\nfalse\ntrue\nfalse\n4\n0\n12\nsquare\n6\nnil\nrect\n",
+    );
+
+    let errors = [
+        ("enum E { A(x) } E.A();", "Expected 1 arguments but got 0."),
+        ("enum E { A } E.B;", "Undefined property 'B'."),
+        ("enum E { A } E.B();", "Undefined property 'B'."),
+        ("enum E { A(x) } E.A(1).y;", "Undefined property 'y'."),
+        (
+            "enum E { A(x) } match (E.A(1)) { E.A(x, y) => print x; }",
+            "Pattern has 2 values but E.A has 1.",
+        ),
+        (
+            "class C {} match (1) { C.x => print 1; }",
+            "Undefined property 'x'.",
+        ),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            run(source).0,
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        );
+    }
+    assert_eq!(
+        syntax_errors("enum E { A, A }"),
+        vec!["[line 1] Error at 'A': Already a variant with this name in this enum."]
+    );
+}
+
+#[test]
+fn match_warns_about_missing_variants() {
+    let warnings = |source: &str| -> Vec<String> {
+        let (_, warnings) = compile_with_warnings(String::from(source)).unwrap();
+        warnings.iter().map(|w| w.to_string()).collect()
+    };
+
+    assert_eq!(
+        warnings(
+            "enum Shape { Circle(r), Rect(w, h), Empty }\n\
+             match (Shape.Empty) { Shape.Circle(0) => print 0; Shape.Rect => print 1; }"
+        ),
+        vec![
+            "[line 2] Warning at 'match': Match has no arm for Shape.Circle.",
+            "[line 2] Warning at 'match': Match has no arm for Shape.Empty."
+        ]
+    );
+    assert_eq!(
+        warnings(
+            "enum E { A, B }\nmatch (E.A) { E.A => print 1; E.A => print 2; E.B => print 3; }"
+        ),
+        vec!["[line 2] Warning at 'E': Unreachable match arm."]
+    );
+    assert!(warnings("enum E { A, B } match (E.A) { E.A => print 1; _ => print 2; }").is_empty());
+    assert!(warnings("match (1) { }").is_empty());
+}
+
+#[test]
+fn assertions() {
+    assert_prints(
+        "assert 1 + 1 == 2; assert true, missing(); print \"passed\";",
+        "passed\n",
+    );
+
+    let errors = [
+        (
+            "var x = 2;\n  assert x ==  3;",
+            "Assertion failed: x ==  3 (line 2, columns 10-16).",
+        ),
+        (
+            "assert nil, \"value \" + \"missing\";",
+            "Assertion failed: nil (line 1, columns 8-10): value missing",
+        ),
+        (
+            "assert \"é\" == \"e\"\n  or false;",
+            "Assertion failed: \"é\" == \"e\"\n  or false (lines 1-2).",
+        ),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            run(source).0,
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        );
+    }
+    assert_eq!(
+        syntax_errors("assert 1 \"message\";"),
+        vec!["[line 1] Error at '\"message\"': Expect ';' after assertion."]
+    );
+}
+
+#[test]
+fn test_blocks_are_skipped_in_normal_runs() {
+    assert_prints(
+        "test \"never runs\" { print \"inside\"; } \
+         class A { test() { return \"method\"; } } var test = A().test; print test();",
+        "method\n",
+    );
+    assert_eq!(
+        syntax_errors("fun f() { test \"nested\" { } }")[0],
+        "[line 1] Error at 'test': Test blocks must be at the top level."
+    );
+}
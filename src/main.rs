@@ -1,16 +1,43 @@
-use log::{error, info};
+use log::error;
 use simplelog::*;
 use std::env;
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::{error::Error, fs};
 
+use chunk::disassembler::disassemble_function;
+use vm::error::InterpretError;
+use vm::Vm;
+
+pub mod chunk;
+pub mod compiler;
+pub mod object;
+// the AST parser is mid refactor and doesn't compile, keep it out of the build until it does
+#[cfg(any())]
 pub mod parser;
 pub mod scanner;
 pub mod token;
+pub mod value;
+pub mod vm;
+
+struct Options {
+    /// Print the compiled bytecode instead of running it
+    disassemble: bool,
+    /// Where imports are looked up after the importing file's directory
+    search_paths: Vec<PathBuf>,
+    script: Option<String>,
+    /// Passed to the script, everything after its path
+    script_args: Vec<String>,
+    /// Run without file, stdin, environment or exit natives
+    sandbox: bool,
+    /// `rlox test` runs the test blocks in these files instead of a script
+    test_files: Vec<String>,
+    /// Only tests whose names contain this run
+    test_filter: String,
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-    parser::ast_printer_test();
-    return Ok(());
     // initialize simple log
     CombinedLogger::init(vec![TermLogger::new(
         LevelFilter::Info,
@@ -21,36 +48,183 @@ fn main() -> Result<(), Box<dyn Error>> {
     .unwrap();
 
     let args: Vec<String> = env::args().collect();
-    match args.len() {
-        1 => run_prompt(),
-        2 => {
-            let buffer = fs::read_to_string(&args[1]).unwrap();
+    if args.get(1).map(String::as_str) == Some("test") {
+        let options = parse_test_args(&args[1..]);
+        run_tests(&options);
+        return Ok(());
+    }
+
+    let options = parse_args(&args);
+    match &options.script {
+        Some(path) => run_file(path, &options),
+        None => run_prompt(&options),
+    }
+
+    Ok(())
+}
+
+fn parse_args(args: &[String]) -> Options {
+    let mut options = default_options();
+
+    // flags come before the script path, arguments after it are the script's own
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            // the bytecode vm is the only engine until the tree-walking interpreter exists
+            "--engine=vm" => {}
+            "--engine=tree" => {
+                error!("The tree-walking interpreter is not available yet.");
+                usage();
+            }
+            "--disassemble" => options.disassemble = true,
+            "--sandbox" => options.sandbox = true,
+            flag if flag.starts_with("--lox-path=") => {
+                let paths = &flag["--lox-path=".len()..];
+                options.search_paths.extend(env::split_paths(paths));
+            }
+            flag if flag.starts_with("--") => usage(),
+            path => {
+                options.script = Some(String::from(path));
+                options.script_args = args.cloned().collect();
+                break;
+            }
+        }
+    }
+
+    add_lox_path(&mut options);
+    options
+}
+
+fn default_options() -> Options {
+    Options {
+        disassemble: false,
+        search_paths: Vec::new(),
+        script: None,
+        script_args: Vec::new(),
+        sandbox: false,
+        test_files: Vec::new(),
+        test_filter: String::new(),
+    }
+}
+
+// rlox test [--filter=name] [--sandbox] [--lox-path=dirs] files...
+fn parse_test_args(args: &[String]) -> Options {
+    let mut options = default_options();
+    for arg in args.iter().skip(1) {
+        match arg.as_str() {
+            "--sandbox" => options.sandbox = true,
+            flag if flag.starts_with("--filter=") => {
+                options.test_filter = String::from(&flag["--filter=".len()..]);
+            }
+            flag if flag.starts_with("--lox-path=") => {
+                let paths = &flag["--lox-path=".len()..];
+                options.search_paths.extend(env::split_paths(paths));
+            }
+            flag if flag.starts_with("--") => usage(),
+            path => options.test_files.push(String::from(path)),
+        }
+    }
 
-            let result = scan(buffer);
+    if options.test_files.is_empty() {
+        usage();
+    }
+    add_lox_path(&mut options);
+    options
+}
+
+// the flag's directories are searched before the environment's
+fn add_lox_path(options: &mut Options) {
+    if let Some(paths) = env::var_os("LOX_PATH") {
+        options.search_paths.extend(env::split_paths(&paths));
+    }
+}
+
+fn usage() -> ! {
+    error!("Usage: rlox [--engine=vm] [--disassemble] [--sandbox] [--lox-path=dirs] [script] [args...]");
+    error!("       rlox test [--filter=name] [--sandbox] [--lox-path=dirs] files...");
+    std::process::exit(64);
+}
+
+fn run_file(path: &str, options: &Options) {
+    let buffer = fs::read_to_string(path).unwrap();
+
+    let mut vm = new_vm(options);
+    vm.set_script_path(Path::new(path));
+    match run(buffer, options, &mut vm) {
+        Ok(()) => {}
+        Err(InterpretError::CompileError(_)) => std::process::exit(65),
+        Err(InterpretError::RuntimeError(_)) => std::process::exit(70),
+        Err(InterpretError::Exit(code)) => std::process::exit(code),
+    }
+}
+
+/// Runs the test blocks in each file, exits with 1 when any of them fail
+fn run_tests(options: &Options) {
+    let (mut passed, mut failed) = (0, 0);
+    for path in &options.test_files {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                error!("Could not read '{}': {}.", path, err);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let mut vm = new_vm(options);
+        vm.set_script_path(Path::new(path));
+        vm.enable_tests();
+        // the file's top level runs first, a file that fails there has no tests to run
+        let mut results = match vm.interpret(source) {
+            Ok(()) => vm.run_tests(&options.test_filter),
+            Err(error) => vec![(Rc::from("<top level>"), Err(error))],
+        };
+        for (name, result) in results.drain(..) {
             match result {
-                Ok(tokens) => {
-                    for token in tokens.iter() {
-                        info!("{:?}", token);
-                    }
+                Ok(()) => {
+                    println!("ok   {} > {}", path, name);
+                    passed += 1;
+                }
+                Err(InterpretError::Exit(code)) => std::process::exit(code),
+                Err(error) => {
+                    println!("FAIL {} > {}: {}", path, name, error);
+                    failed += 1;
                 }
-                Err(error) => error!("halp {:?}", error),
             }
         }
-        _ => {
-            error!("Usage: rlox[script]");
-            std::process::exit(64);
-        }
     }
 
-    Ok(())
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        std::process::exit(1);
+    }
 }
 
-fn scan(src: String) -> Result<Vec<token::Token>, scanner::error::ScanError> {
-    let mut sr = scanner::Scanner::new(src);
-    sr.scan_tokens()
+fn new_vm(options: &Options) -> Vm<io::Stdout> {
+    let mut vm = Vm::new(io::stdout());
+    for path in &options.search_paths {
+        vm.add_search_path(path.clone());
+    }
+    vm.set_args(options.script_args.clone());
+    if options.sandbox {
+        vm.disable_io();
+    }
+    vm
 }
 
-fn run_prompt() {
+fn run(source: String, options: &Options, vm: &mut Vm<io::Stdout>) -> Result<(), InterpretError> {
+    if options.disassemble {
+        let script = compiler::compile(source).map_err(InterpretError::CompileError)?;
+        print!("{}", disassemble_function(&script));
+        return Ok(());
+    }
+
+    vm.interpret(source)
+}
+
+fn run_prompt(options: &Options) {
+    // one vm for the whole session so globals carry over between lines
+    let mut vm = new_vm(options);
     loop {
         print!("> ");
         io::stdout().flush().unwrap();
@@ -62,14 +236,9 @@ fn run_prompt() {
         }
         // Pop off newline at end
         buffer.pop();
-        let result = scan(buffer);
-        match result {
-            Ok(tokens) => {
-                for token in tokens.iter() {
-                    info!("{:?}", token);
-                }
-            }
-            Err(error) => error!("halp {:?}", error),
+        // errors have already been reported, keep the session going
+        if let Err(InterpretError::Exit(code)) = run(buffer, options, &mut vm) {
+            std::process::exit(code);
         }
     }
 }
@@ -79,5 +248,5 @@ fn error(line: usize, msg: &str) {
 }
 
 fn report(line: usize, where_at: &str, msg: &str) {
-    error!("[line {}] Error: {} {}", line, where_at, msg);
+    error!("[line {}] Error{}: {}", line, where_at, msg);
 }
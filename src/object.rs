@@ -0,0 +1,474 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::chunk::Chunk;
+use crate::value::Value;
+use crate::vm::NativeContext;
+
+#[derive(Debug, Default)]
+pub struct Function {
+    /// Parameters before the rest parameter, including the ones with defaults
+    pub arity: usize,
+    /// How many of the last parameters have defaults
+    pub optional: usize,
+    /// Extra arguments are collected into a list in the slot after the other parameters
+    pub has_rest: bool,
+    /// Names of the parameters counted by arity, for named arguments
+    pub parameters: Vec<Rc<str>>,
+    /// Contains a yield, so calling it makes a generator instead of running the body
+    pub is_generator: bool,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    /// None for the top level script
+    pub name: Option<Rc<str>>,
+}
+
+impl Function {
+    pub fn new(name: Option<Rc<str>>) -> Self {
+        Self {
+            arity: 0,
+            optional: 0,
+            has_rest: false,
+            parameters: Vec::new(),
+            is_generator: false,
+            upvalue_count: 0,
+            chunk: Chunk::new(),
+            name,
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<script>"),
+        }
+    }
+}
+
+/// A variable captured by a closure
+#[derive(Debug)]
+pub enum Upvalue {
+    /// Still lives on the vm stack at this slot
+    Open(usize),
+    /// Moved off the stack once its scope ended
+    Closed(Value),
+}
+
+/// A function together with the variables it captured, this is what the vm actually calls
+///
+/// Objects are reference counted, so a cycle through a captured variable is never freed. The
+/// common case is a local recursive function, which captures itself. Known leak until the vm
+/// gets a tracing garbage collector.
+#[derive(Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    /// The module whose globals the function sees
+    pub module: Rc<Module>,
+}
+
+impl Closure {
+    pub fn new(function: Rc<Function>, module: Rc<Module>) -> Self {
+        Self {
+            function,
+            upvalues: Vec::new(),
+            module,
+        }
+    }
+}
+
+impl fmt::Display for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.function)
+    }
+}
+
+/// `start..end` leaves out end, `start..=end` includes it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub inclusive: bool,
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{}{}{}", self.start, operator, self.end)
+    }
+}
+
+/// What a for-in loop steps through, made by iter() on a list, map, string or range
+#[derive(Debug)]
+pub enum LoxIterator {
+    /// Sees elements pushed while it runs
+    List {
+        list: Rc<RefCell<Vec<Value>>>,
+        index: usize,
+    },
+    /// Map keys and string characters, taken when the iterator is made
+    Values {
+        values: Vec<Value>,
+        index: usize,
+    },
+    Range {
+        next: f64,
+        range: Range,
+    },
+}
+
+impl Iterator for LoxIterator {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        match self {
+            LoxIterator::List { list, index } => {
+                let value = list.borrow().get(*index).cloned();
+                *index += 1;
+                value
+            }
+            LoxIterator::Values { values, index } => {
+                let value = values.get(*index).cloned();
+                *index += 1;
+                value
+            }
+            LoxIterator::Range { next, range } => {
+                let value = *next;
+                if value < range.end || range.inclusive && value == range.end {
+                    *next += 1.0;
+                    Some(Value::Number(value))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// An enum declaration, its variants are reached as properties
+#[derive(Debug)]
+pub struct Enum {
+    pub name: Rc<str>,
+    pub variants: Vec<Rc<EnumVariant>>,
+}
+
+impl Enum {
+    /// What `Enum.name` gives, the value itself for a variant without fields or else the
+    /// function that makes one
+    pub fn variant(&self, name: &str) -> Option<Value> {
+        let variant = self
+            .variants
+            .iter()
+            .find(|variant| &*variant.name == name)?;
+        if variant.fields.is_empty() {
+            Some(Value::EnumValue(Rc::new(EnumValue {
+                variant: variant.clone(),
+                values: Vec::new(),
+            })))
+        } else {
+            Some(Value::EnumVariant(variant.clone()))
+        }
+    }
+}
+
+impl fmt::Display for Enum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<enum {}>", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct EnumVariant {
+    pub enum_name: Rc<str>,
+    pub name: Rc<str>,
+    pub fields: Vec<Rc<str>>,
+}
+
+impl fmt::Display for EnumVariant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.enum_name, self.name)
+    }
+}
+
+/// A variant with its field values, compared by variant and values rather than identity
+#[derive(Debug)]
+pub struct EnumValue {
+    pub variant: Rc<EnumVariant>,
+    pub values: Vec<Value>,
+}
+
+impl fmt::Display for EnumValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.variant)?;
+        if self.variant.fields.is_empty() {
+            return Ok(());
+        }
+
+        let values: Vec<String> = self.values.iter().map(Value::repr).collect();
+        write!(f, "({})", values.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeneratorState {
+    Suspended,
+    Running,
+    Done,
+}
+
+/// A call to a generator function, paused at its start or at a yield
+#[derive(Debug)]
+pub struct Generator {
+    pub closure: Rc<Closure>,
+    pub state: GeneratorState,
+    /// The frame's stack slots while it is paused
+    pub stack: Vec<Value>,
+    pub ip: usize,
+    /// Parameters left for their defaults, as in a call frame
+    pub missing: Vec<bool>,
+    /// Stack offset and catch ip of each try block the generator paused in
+    pub handlers: Vec<(usize, usize)>,
+}
+
+impl Generator {
+    pub fn new(closure: Rc<Closure>, stack: Vec<Value>, missing: Vec<bool>) -> Self {
+        Self {
+            closure,
+            state: GeneratorState::Suspended,
+            stack,
+            ip: 0,
+            missing,
+            handlers: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.closure.function.name {
+            Some(name) => write!(f, "<generator {}>", name),
+            None => write!(f, "<generator>"),
+        }
+    }
+}
+
+/// A script or imported file, its globals are what importing it exposes
+pub struct Module {
+    pub name: Rc<str>,
+    /// Imports inside the module are looked up next to this file, None for the repl
+    pub path: Option<PathBuf>,
+    pub globals: RefCell<HashMap<Rc<str>, Value>>,
+    /// Globals declared with const, which can't be assigned or declared again
+    pub constants: RefCell<HashSet<Rc<str>>>,
+}
+
+impl Module {
+    pub fn new(name: Rc<str>, path: Option<PathBuf>) -> Self {
+        Self {
+            name,
+            path,
+            globals: RefCell::new(HashMap::new()),
+            constants: RefCell::new(HashSet::new()),
+        }
+    }
+}
+
+// globals usually hold closures that point back at the module, so they are left out
+impl fmt::Debug for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Module({})", self.name)
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<module {}>", self.name)
+    }
+}
+
+pub type NativeFn = fn(&mut NativeContext, &[Value]) -> Result<Value, String>;
+
+pub struct Native {
+    pub name: Rc<str>,
+    /// None for natives that take any number of arguments
+    pub arity: Option<usize>,
+    pub function: NativeFn,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Native({})", self.name)
+    }
+}
+
+impl fmt::Display for Native {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+#[derive(Debug)]
+pub struct Class {
+    pub name: Rc<str>,
+    /// Inherited methods are copied into methods, this is kept for reflection
+    pub superclass: Option<Rc<RefCell<Class>>>,
+    pub methods: HashMap<Rc<str>, Rc<Closure>>,
+    /// Methods run on property access, declared without a parameter list
+    pub getters: HashMap<Rc<str>, Rc<Closure>>,
+    /// Methods called on the class itself, with this bound to the class
+    pub class_methods: HashMap<Rc<str>, Rc<Closure>>,
+}
+
+impl Class {
+    pub fn new(name: Rc<str>) -> Self {
+        Self {
+            name,
+            superclass: None,
+            methods: HashMap::new(),
+            getters: HashMap::new(),
+            class_methods: HashMap::new(),
+        }
+    }
+
+    /// Copies every kind of method from other, replacing those with the same name
+    pub fn copy_methods(&mut self, other: &Class) {
+        self.methods.extend(other.methods.clone());
+        self.getters.extend(other.getters.clone());
+        self.class_methods.extend(other.class_methods.clone());
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct Instance {
+    pub class: Rc<RefCell<Class>>,
+    pub fields: HashMap<Rc<str>, Value>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<RefCell<Class>>) -> Self {
+        Self {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} instance", self.class.borrow().name)
+    }
+}
+
+// Only values with a stable notion of equality can be map keys
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum MapKey {
+    Nil,
+    Bool(bool),
+    // bits of the number, with -0 folded into 0 so equal numbers hash alike
+    Number(u64),
+    LoxString(Rc<str>),
+    // instances are keys by identity, the entry keeps the instance alive so the address is unique
+    Instance(usize),
+}
+
+impl MapKey {
+    fn new(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Nil => Ok(MapKey::Nil),
+            Value::Bool(value) => Ok(MapKey::Bool(*value)),
+            Value::Number(value) if value.is_nan() => Err(String::from("Map keys can't be NaN.")),
+            Value::Number(value) => Ok(MapKey::Number((value + 0.0).to_bits())),
+            Value::LoxString(value) => Ok(MapKey::LoxString(value.clone())),
+            Value::Instance(instance) => Ok(MapKey::Instance(Rc::as_ptr(instance) as usize)),
+            _ => Err(String::from(
+                "Map keys must be strings, numbers, booleans, nil or instances.",
+            )),
+        }
+    }
+}
+
+/// A hash map that iterates in insertion order, so printing one gives the same output every run
+#[derive(Debug, Default)]
+pub struct Map {
+    entries: Vec<(Value, Value)>,
+    // position of each key in entries
+    indexes: HashMap<MapKey, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Errors when key can't be a map key
+    pub fn get(&self, key: &Value) -> Result<Option<&Value>, String> {
+        let index = self.indexes.get(&MapKey::new(key)?);
+        Ok(index.map(|index| &self.entries[*index].1))
+    }
+
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), String> {
+        let map_key = MapKey::new(&key)?;
+        match self.indexes.get(&map_key) {
+            Some(index) => self.entries[*index].1 = value,
+            None => {
+                self.indexes.insert(map_key, self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the value that was stored under key
+    pub fn remove(&mut self, key: &Value) -> Result<Option<Value>, String> {
+        let index = match self.indexes.remove(&MapKey::new(key)?) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+
+        let (_, value) = self.entries.remove(index);
+        // every entry after the removed one moved down a place
+        for moved in self.indexes.values_mut() {
+            if *moved > index {
+                *moved -= 1;
+            }
+        }
+        Ok(Some(value))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+/// A method looked up on an instance, remembering the instance it was accessed from
+#[derive(Debug)]
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: Rc<Closure>,
+}
+
+impl fmt::Display for BoundMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.method)
+    }
+}
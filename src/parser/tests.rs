@@ -18,10 +18,10 @@ fn init_log() {
 fn read_string_happy_path() {
     let expr = Binary {
         left: Box::new(Expr::Unary(Unary {
-            operator: Token::new(TokenType::Minus),
+            operator: Token::new(TokenType::Minus, "-", 1),
             right: Box::new(Expr::Literal(Literal(LiteralType::Integer(123)))),
         })),
-        operator: Token::new(TokenType::Star),
+        operator: Token::new(TokenType::Star, "*", 1),
         right: Box::new(Expr::Grouping(Grouping {
             expression: Box::new(Expr::Literal(Literal(LiteralType::Float(45.67)))),
         })),
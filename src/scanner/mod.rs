@@ -12,6 +12,7 @@ pub struct Scanner<'a> {
     source_len: usize,
     tokens: Vec<Token>,
     line: usize,
+    // start and current are byte offsets into source so slicing stays on char boundaries
     start: usize,
     current: usize,
     keyword_map: HashMap<&'a str, TokenType>,
@@ -22,21 +23,33 @@ impl<'a> Scanner<'a> {
     pub fn new(source: String) -> Self {
         let keyword_map = HashMap::from([
             ("and", TokenType::And),
+            ("assert", TokenType::Assert),
+            ("catch", TokenType::Catch),
             ("class", TokenType::Class),
+            ("const", TokenType::Const),
             ("else", TokenType::Else),
+            ("enum", TokenType::Enum),
             ("false", TokenType::False),
+            ("finally", TokenType::Finally),
             ("for", TokenType::For),
             ("fun", TokenType::Fun),
             ("if", TokenType::If),
+            ("import", TokenType::Import),
+            ("in", TokenType::In),
+            ("match", TokenType::Match),
             ("nil", TokenType::Nil),
             ("or", TokenType::Or),
             ("print", TokenType::Print),
             ("return", TokenType::Return),
             ("super", TokenType::Super),
             ("this", TokenType::This),
+            ("throw", TokenType::Throw),
+            ("trait", TokenType::Trait),
             ("true", TokenType::True),
+            ("try", TokenType::Try),
             ("var", TokenType::Var),
             ("while", TokenType::While),
+            ("yield", TokenType::Yield),
         ]);
 
         let source_len = source.len();
@@ -58,10 +71,12 @@ impl<'a> Scanner<'a> {
             self.scan_token();
         }
 
-        self.tokens.push(Token::new(TokenType::EOF));
+        let mut eof = Token::new(TokenType::EOF, "", self.line);
+        eof.offset = self.source_len;
+        self.tokens.push(eof);
 
-        if self.error.is_some() {
-            return Err(self.error.unwrap());
+        if let Some(error) = self.error {
+            return Err(error);
         }
 
         Ok(self.tokens.to_vec())
@@ -75,8 +90,25 @@ impl<'a> Scanner<'a> {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
+            ':' => self.add_token(TokenType::Colon),
             ',' => self.add_token(TokenType::Comma),
-            '.' => self.add_token(TokenType::Dot),
+            '.' => {
+                if self.peek_is('.') && self.peek_next() == '.' {
+                    self.current += 2;
+                    self.add_token(TokenType::Ellipsis);
+                } else if self.match_char('.') {
+                    let token_type = if self.match_char('=') {
+                        TokenType::DotDotEqual
+                    } else {
+                        TokenType::DotDot
+                    };
+                    self.add_token(token_type);
+                } else {
+                    self.add_token(TokenType::Dot);
+                }
+            }
             '-' => self.add_token(TokenType::Minus),
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::Semicolon),
@@ -92,6 +124,8 @@ impl<'a> Scanner<'a> {
             '=' => {
                 let token_type = if self.match_char('=') {
                     TokenType::EqualEqual
+                } else if self.match_char('>') {
+                    TokenType::Arrow
                 } else {
                     TokenType::Equal
                 };
@@ -141,7 +175,10 @@ impl<'a> Scanner<'a> {
     }
 
     fn add_token(&mut self, token_type: TokenType) {
-        self.tokens.push(Token::new(token_type));
+        let lexeme = &self.source[self.start..self.current];
+        let mut token = Token::new(token_type, lexeme, self.line);
+        token.offset = self.start;
+        self.tokens.push(token);
     }
 
     fn is_at_end(&self) -> bool {
@@ -149,11 +186,12 @@ impl<'a> Scanner<'a> {
     }
 
     fn advance(&mut self) -> char {
-        self.current += 1;
-        self.source
+        let c = self.source[self.current..]
             .chars()
-            .nth(self.current - 1)
-            .expect("Failed to advance")
+            .next()
+            .expect("Failed to advance");
+        self.current += c.len_utf8();
+        c
     }
 
     fn match_char(&mut self, char_to_match: char) -> bool {
@@ -170,7 +208,15 @@ impl<'a> Scanner<'a> {
     }
 
     fn peek(&self) -> char {
-        self.source.chars().nth(self.current).unwrap()
+        self.source[self.current..].chars().next().unwrap()
+    }
+
+    fn peek_is(&self, char_to_match: char) -> bool {
+        !self.is_at_end() && self.peek() == char_to_match
+    }
+
+    fn peek_next(&self) -> char {
+        self.source[self.current..].chars().nth(1).unwrap_or('\0')
     }
 
     fn read_string(&mut self) {
@@ -192,10 +238,9 @@ impl<'a> Scanner<'a> {
             return;
         }
 
-        self.tokens
-            .push(Token::new(TokenType::Literal(LiteralType::LoxString(
-                String::from(&self.source[self.start..self.current]),
-            ))));
+        self.add_token(TokenType::Literal(LiteralType::LoxString(String::from(
+            &self.source[self.start..self.current],
+        ))));
     }
 
     fn read_number(&mut self) {
@@ -205,7 +250,8 @@ impl<'a> Scanner<'a> {
             self.advance();
         }
 
-        if !self.is_at_end() && self.peek() == '.' {
+        // a second dot means a range operator follows, not a decimal
+        if self.peek_is('.') && self.peek_next() != '.' {
             // consume dot
             self.advance();
 
@@ -224,21 +270,13 @@ impl<'a> Scanner<'a> {
 
         let number_value = String::from(&self.source[self.start..self.current]);
 
-        if is_float {
-            let float_number_value = number_value.parse::<f64>().unwrap();
+        // Lox numbers are doubles, so an integer too big for isize is still a valid literal
+        let literal = match (is_float, number_value.parse::<isize>()) {
+            (false, Ok(int_number_value)) => LiteralType::Integer(int_number_value),
+            _ => LiteralType::Float(number_value.parse::<f64>().unwrap()),
+        };
 
-            self.tokens
-                .push(Token::new(TokenType::Literal(LiteralType::Float(
-                    float_number_value,
-                ))));
-        } else {
-            let int_number_value = number_value.parse::<isize>().unwrap();
-
-            self.tokens
-                .push(Token::new(TokenType::Literal(LiteralType::Integer(
-                    int_number_value,
-                ))));
-        }
+        self.add_token(TokenType::Literal(literal));
     }
 
     fn read_identifier(&mut self) {
@@ -248,12 +286,11 @@ impl<'a> Scanner<'a> {
 
         let identifier_value = String::from(&self.source[self.start..self.current]);
         if let Some(keyword_token) = self.keyword_map.get(identifier_value.as_str()) {
-            self.tokens.push(Token::new(keyword_token.clone()));
+            self.add_token(keyword_token.clone());
         } else {
-            self.tokens
-                .push(Token::new(TokenType::Literal(LiteralType::Identifier(
-                    identifier_value,
-                ))));
+            self.add_token(TokenType::Literal(LiteralType::Identifier(
+                identifier_value,
+            )));
         }
     }
 }
@@ -263,9 +300,9 @@ fn is_alpha_numeric(check_param: char) -> bool {
 }
 
 fn is_digit(check_param: char) -> bool {
-    ('0'..='9').contains(&check_param)
+    check_param.is_ascii_digit()
 }
 
 fn is_alpha(check_param: char) -> bool {
-    ('A'..='Z').contains(&check_param) || ('a'..='z').contains(&check_param) || check_param == '_'
+    check_param.is_ascii_alphabetic() || check_param == '_'
 }
@@ -14,6 +14,12 @@ fn init_log() {
     .unwrap_or(());
 }
 
+fn token_types(src: &str) -> Vec<TokenType> {
+    let mut sr = Scanner::new(String::from(src));
+    let tokens = sr.scan_tokens().unwrap();
+    tokens.into_iter().map(|t| t.token_type).collect()
+}
+
 #[test]
 fn read_string_happy_path() {
     let string = String::from(
@@ -53,7 +59,7 @@ fn read_string_errors_on_unterminated() {
 
 #[test]
 fn read_integer_happy_path() {
-    let numbers = vec!["123", "3456098", "98", "1"];
+    let numbers = ["123", "3456098", "98", "1"];
     let mut sr = Scanner::new(numbers.join(" "));
     let result = sr.scan_tokens();
     assert!(result.is_ok());
@@ -72,7 +78,7 @@ fn read_integer_happy_path() {
 
 #[test]
 fn read_float_happy_path() {
-    let numbers = vec!["123.9345", "3456098.4", "98.0000001", "0.45"];
+    let numbers = ["123.9345", "3456098.4", "98.0000001", "0.45"];
     let mut sr = Scanner::new(numbers.join(" "));
     let result = sr.scan_tokens();
     assert!(result.is_ok());
@@ -89,6 +95,14 @@ fn read_float_happy_path() {
     assert_eq!(tokens[tokens.len() - 1].token_type, TokenType::EOF);
 }
 
+#[test]
+fn read_integer_too_large_for_isize() {
+    assert_eq!(
+        token_types("99999999999999999999"),
+        vec![TokenType::Literal(LiteralType::Float(1e20)), TokenType::EOF]
+    );
+}
+
 #[test]
 fn read_float_cannot_end_with_decimal() {
     let mut sr = Scanner::new(String::from(r#"42."#));
@@ -108,3 +122,104 @@ fn uknown_token_error() {
     let err = result.unwrap_err();
     assert_eq!(err, error::ScanError::UnknownToken);
 }
+
+#[test]
+fn map_literal_and_index_tokens() {
+    assert_eq!(
+        token_types(r#"{"a": 1}[key]"#),
+        vec![
+            TokenType::LeftBrace,
+            TokenType::Literal(LiteralType::LoxString(String::from(r#""a""#))),
+            TokenType::Colon,
+            TokenType::Literal(LiteralType::Integer(1)),
+            TokenType::RightBrace,
+            TokenType::LeftBracket,
+            TokenType::Literal(LiteralType::Identifier(String::from("key"))),
+            TokenType::RightBracket,
+            TokenType::EOF,
+        ]
+    );
+}
+
+#[test]
+fn arrow_function_tokens() {
+    assert_eq!(
+        token_types("(a) => a >= 2 == b = c"),
+        vec![
+            TokenType::LeftParen,
+            TokenType::Literal(LiteralType::Identifier(String::from("a"))),
+            TokenType::RightParen,
+            TokenType::Arrow,
+            TokenType::Literal(LiteralType::Identifier(String::from("a"))),
+            TokenType::GreaterEqual,
+            TokenType::Literal(LiteralType::Integer(2)),
+            TokenType::EqualEqual,
+            TokenType::Literal(LiteralType::Identifier(String::from("b"))),
+            TokenType::Equal,
+            TokenType::Literal(LiteralType::Identifier(String::from("c"))),
+            TokenType::EOF,
+        ]
+    );
+}
+
+#[test]
+fn rest_parameter_and_named_argument_tokens() {
+    assert_eq!(
+        token_types("(a, ...rest) f(b: 3) a.b.."),
+        vec![
+            TokenType::LeftParen,
+            TokenType::Literal(LiteralType::Identifier(String::from("a"))),
+            TokenType::Comma,
+            TokenType::Ellipsis,
+            TokenType::Literal(LiteralType::Identifier(String::from("rest"))),
+            TokenType::RightParen,
+            TokenType::Literal(LiteralType::Identifier(String::from("f"))),
+            TokenType::LeftParen,
+            TokenType::Literal(LiteralType::Identifier(String::from("b"))),
+            TokenType::Colon,
+            TokenType::Literal(LiteralType::Integer(3)),
+            TokenType::RightParen,
+            TokenType::Literal(LiteralType::Identifier(String::from("a"))),
+            TokenType::Dot,
+            TokenType::Literal(LiteralType::Identifier(String::from("b"))),
+            TokenType::DotDot,
+            TokenType::EOF,
+        ]
+    );
+}
+
+#[test]
+fn for_in_range_tokens() {
+    assert_eq!(
+        token_types("for (var x in 0..10) 1.5..=n"),
+        vec![
+            TokenType::For,
+            TokenType::LeftParen,
+            TokenType::Var,
+            TokenType::Literal(LiteralType::Identifier(String::from("x"))),
+            TokenType::In,
+            TokenType::Literal(LiteralType::Integer(0)),
+            TokenType::DotDot,
+            TokenType::Literal(LiteralType::Integer(10)),
+            TokenType::RightParen,
+            TokenType::Literal(LiteralType::Float(1.5)),
+            TokenType::DotDotEqual,
+            TokenType::Literal(LiteralType::Identifier(String::from("n"))),
+            TokenType::EOF,
+        ]
+    );
+}
+
+#[test]
+fn read_string_utf8() {
+    let string = String::from(r#""héllo wörld ✓""#);
+    assert_eq!(
+        token_types(&format!("{} + name", string)),
+        vec![
+            TokenType::Literal(LiteralType::LoxString(string)),
+            TokenType::Plus,
+            TokenType::Literal(LiteralType::Identifier(String::from("name"))),
+            TokenType::EOF,
+        ]
+    );
+}
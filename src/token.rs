@@ -15,6 +15,9 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -27,27 +30,44 @@ pub enum TokenType {
     BangEqual,
     Equal,
     EqualEqual,
+    Arrow,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
+    DotDot,
+    // three character tokens
+    DotDotEqual,
+    Ellipsis,
     // keywords
     And,
+    Assert,
+    Catch,
     Class,
+    Const,
     Else,
+    Enum,
     False,
+    Finally,
     Fun,
     For,
     If,
+    Import,
+    In,
+    Match,
     Nil,
     Or,
     Print,
     Return,
     Super,
     This,
+    Throw,
+    Trait,
     True,
+    Try,
     Var,
     While,
+    Yield,
     EOF,
     // literals
     Literal(LiteralType),
@@ -57,11 +77,21 @@ pub enum TokenType {
 // 'a says the literals attached to a TokenType must live the lifetime of the token
 pub struct Token {
     pub token_type: TokenType,
+    /// The source text the token was scanned from
+    pub lexeme: String,
+    pub line: usize,
+    /// Byte offset of the lexeme in the source, 0 for tokens that weren't scanned
+    pub offset: usize,
 }
 
 impl Token {
-    pub fn new(token_type: TokenType) -> Token {
-        Token { token_type }
+    pub fn new(token_type: TokenType, lexeme: &str, line: usize) -> Token {
+        Token {
+            token_type,
+            lexeme: String::from(lexeme),
+            line,
+            offset: 0,
+        }
     }
 }
 
@@ -71,14 +101,79 @@ impl fmt::Display for Token {
     }
 }
 
+// Displays the source text the token was scanned from
 impl fmt::Display for TokenType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        let lexeme = match self {
+            TokenType::LeftParen => "(",
+            TokenType::RightParen => ")",
+            TokenType::LeftBrace => "{",
+            TokenType::RightBrace => "}",
+            TokenType::LeftBracket => "[",
+            TokenType::RightBracket => "]",
+            TokenType::Colon => ":",
+            TokenType::Comma => ",",
+            TokenType::Dot => ".",
+            TokenType::Minus => "-",
+            TokenType::Plus => "+",
+            TokenType::Semicolon => ";",
+            TokenType::Slash => "/",
+            TokenType::Star => "*",
+            TokenType::Bang => "!",
+            TokenType::BangEqual => "!=",
+            TokenType::Equal => "=",
+            TokenType::EqualEqual => "==",
+            TokenType::Arrow => "=>",
+            TokenType::Greater => ">",
+            TokenType::GreaterEqual => ">=",
+            TokenType::Less => "<",
+            TokenType::LessEqual => "<=",
+            TokenType::DotDot => "..",
+            TokenType::DotDotEqual => "..=",
+            TokenType::Ellipsis => "...",
+            TokenType::And => "and",
+            TokenType::Assert => "assert",
+            TokenType::Catch => "catch",
+            TokenType::Class => "class",
+            TokenType::Const => "const",
+            TokenType::Else => "else",
+            TokenType::Enum => "enum",
+            TokenType::False => "false",
+            TokenType::Finally => "finally",
+            TokenType::Fun => "fun",
+            TokenType::For => "for",
+            TokenType::If => "if",
+            TokenType::Import => "import",
+            TokenType::In => "in",
+            TokenType::Match => "match",
+            TokenType::Nil => "nil",
+            TokenType::Or => "or",
+            TokenType::Print => "print",
+            TokenType::Return => "return",
+            TokenType::Super => "super",
+            TokenType::This => "this",
+            TokenType::Throw => "throw",
+            TokenType::Trait => "trait",
+            TokenType::True => "true",
+            TokenType::Try => "try",
+            TokenType::Var => "var",
+            TokenType::While => "while",
+            TokenType::Yield => "yield",
+            TokenType::EOF => "",
+            TokenType::Literal(literal) => return write!(f, "{}", literal),
+        };
+
+        write!(f, "{}", lexeme)
     }
 }
 
 impl fmt::Display for LiteralType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        match self {
+            LiteralType::Identifier(name) => write!(f, "{}", name),
+            LiteralType::LoxString(value) => write!(f, "{}", value),
+            LiteralType::Integer(value) => write!(f, "{}", value),
+            LiteralType::Float(value) => write!(f, "{}", value),
+        }
     }
 }
@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::object::*;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    LoxString(Rc<str>),
+    Function(Rc<Function>),
+    Closure(Rc<Closure>),
+    Native(Rc<Native>),
+    Class(Rc<RefCell<Class>>),
+    Instance(Rc<RefCell<Instance>>),
+    BoundMethod(Rc<BoundMethod>),
+    List(Rc<RefCell<Vec<Value>>>),
+    Map(Rc<RefCell<Map>>),
+    Module(Rc<Module>),
+    /// Methods to mix into classes, kept in a class that is never instantiated
+    Trait(Rc<RefCell<Class>>),
+    Range(Range),
+    Iterator(Rc<RefCell<LoxIterator>>),
+    Generator(Rc<RefCell<Generator>>),
+    Enum(Rc<Enum>),
+    /// Makes an EnumValue of a variant with fields when called
+    EnumVariant(Rc<EnumVariant>),
+    EnumValue(Rc<EnumValue>),
+}
+
+impl Value {
+    /// nil and false are falsey, everything else is truthy
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// How the value prints inside a list or map, where strings are quoted
+    pub fn repr(&self) -> String {
+        let mut repr = String::new();
+        write_repr(&mut repr, self, &mut Vec::new()).expect("Failed to format value");
+        repr
+    }
+}
+
+// strings, ranges and enum values compare by content, every other object by identity
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::LoxString(a), Value::LoxString(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::BoundMethod(a), Value::BoundMethod(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::Module(a), Value::Module(b)) => Rc::ptr_eq(a, b),
+            (Value::Trait(a), Value::Trait(b)) => Rc::ptr_eq(a, b),
+            (Value::Range(a), Value::Range(b)) => a == b,
+            (Value::Iterator(a), Value::Iterator(b)) => Rc::ptr_eq(a, b),
+            (Value::Generator(a), Value::Generator(b)) => Rc::ptr_eq(a, b),
+            (Value::Enum(a), Value::Enum(b)) => Rc::ptr_eq(a, b),
+            (Value::EnumVariant(a), Value::EnumVariant(b)) => Rc::ptr_eq(a, b),
+            (Value::EnumValue(a), Value::EnumValue(b)) => {
+                Rc::ptr_eq(&a.variant, &b.variant) && a.values == b.values
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::LoxString(value) => write!(f, "{}", value),
+            Value::Function(function) => write!(f, "{}", function),
+            Value::Closure(closure) => write!(f, "{}", closure),
+            Value::Native(native) => write!(f, "{}", native),
+            Value::Class(class) => write!(f, "{}", class.borrow()),
+            Value::Instance(instance) => write!(f, "{}", instance.borrow()),
+            Value::BoundMethod(bound) => write!(f, "{}", bound),
+            Value::List(_) | Value::Map(_) => write_repr(f, self, &mut Vec::new()),
+            Value::Module(module) => write!(f, "{}", module),
+            Value::Trait(class) => write!(f, "<trait {}>", class.borrow()),
+            Value::Range(range) => write!(f, "{}", range),
+            Value::Iterator(_) => write!(f, "<iterator>"),
+            Value::Generator(generator) => write!(f, "{}", generator.borrow()),
+            Value::Enum(enum_) => write!(f, "{}", enum_),
+            Value::EnumVariant(variant) => write!(f, "<variant {}>", variant),
+            Value::EnumValue(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+// parents holds the lists and maps being written, so one that contains itself prints as [...]
+fn write_repr(f: &mut impl fmt::Write, value: &Value, parents: &mut Vec<usize>) -> fmt::Result {
+    match value {
+        Value::LoxString(value) => write!(f, "\"{}\"", value),
+        Value::List(list) => {
+            let address = Rc::as_ptr(list) as usize;
+            if parents.contains(&address) {
+                return write!(f, "[...]");
+            }
+
+            parents.push(address);
+            write!(f, "[")?;
+            for (i, item) in list.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_repr(f, item, parents)?;
+            }
+            parents.pop();
+            write!(f, "]")
+        }
+        Value::Map(map) => {
+            let address = Rc::as_ptr(map) as usize;
+            if parents.contains(&address) {
+                return write!(f, "{{...}}");
+            }
+
+            parents.push(address);
+            write!(f, "{{")?;
+            for (i, (key, value)) in map.borrow().iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_repr(f, key, parents)?;
+                write!(f, ": ")?;
+                write_repr(f, value, parents)?;
+            }
+            parents.pop();
+            write!(f, "}}")
+        }
+        value => write!(f, "{}", value),
+    }
+}
@@ -0,0 +1,25 @@
+use std::{error::Error, fmt};
+
+use crate::compiler::error::CompileError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InterpretError {
+    /// Source did not compile, errors were already reported
+    CompileError(CompileError),
+    /// Error raised while executing, already reported with its stack trace
+    RuntimeError(String),
+    /// The script called exit() with this code, nothing was reported
+    Exit(i32),
+}
+
+impl Error for InterpretError {}
+
+impl fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterpretError::CompileError(error) => write!(f, "{}", error),
+            InterpretError::RuntimeError(message) => write!(f, "{}", message),
+            InterpretError::Exit(code) => write!(f, "Exited with code {}.", code),
+        }
+    }
+}
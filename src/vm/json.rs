@@ -0,0 +1,408 @@
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::iter::Peekable;
+use std::rc::Rc;
+use std::str::Chars;
+
+use super::natives::NativeContext;
+use crate::object::{Map, Module, Native, NativeFn};
+use crate::value::Value;
+
+// deeper documents are rejected rather than risking the native stack
+const MAX_DEPTH: usize = 512;
+
+/// The json global, a module holding parse and stringify
+pub(super) fn module() -> Module {
+    let module = Module::new(Rc::from("json"), None);
+    let natives: [(&str, Option<usize>, NativeFn); 2] =
+        [("parse", Some(1), parse), ("stringify", None, stringify)];
+    for (name, arity, function) in natives.iter() {
+        let name: Rc<str> = Rc::from(*name);
+        let native = Native {
+            name: name.clone(),
+            arity: *arity,
+            function: *function,
+        };
+        module
+            .globals
+            .borrow_mut()
+            .insert(name, Value::Native(Rc::new(native)));
+    }
+    module
+}
+
+/// parse(string), objects become maps and arrays lists
+fn parse(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let text = match &args[0] {
+        Value::LoxString(text) => text,
+        _ => return Err(String::from("Argument 1 to parse() must be a string.")),
+    };
+
+    let mut parser = Parser {
+        chars: text.chars().peekable(),
+        position: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.peek() {
+        None => Ok(value),
+        Some(_) => Err(parser.error("Expect end of input")),
+    }
+}
+
+/// stringify(value, indent?), compact unless indent is a number of spaces
+fn stringify(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(format!("Expected 1 or 2 arguments but got {}.", args.len()));
+    }
+    let indent = match args.get(1) {
+        None | Some(Value::Nil) => 0,
+        Some(Value::Number(indent)) if *indent >= 0.0 && indent.fract() == 0.0 => *indent as usize,
+        Some(_) => {
+            return Err(String::from(
+                "Argument 2 to stringify() must be a non-negative integer.",
+            ))
+        }
+    };
+
+    let mut writer = Writer {
+        output: String::new(),
+        indent,
+        parents: Vec::new(),
+    };
+    writer.value(&args[0])?;
+    Ok(Value::LoxString(Rc::from(writer.output)))
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    // in chars, for error messages
+    position: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn value(&mut self) -> Result<Value, String> {
+        self.skip_whitespace();
+        match self.chars.peek() {
+            Some('{') => self.nested(Parser::object),
+            Some('[') => self.nested(Parser::array),
+            Some('"') => Ok(Value::LoxString(Rc::from(self.string()?))),
+            Some('-') | Some('0'..='9') => self.number(),
+            Some('t') => self.literal("true", Value::Bool(true)),
+            Some('f') => self.literal("false", Value::Bool(false)),
+            Some('n') => self.literal("null", Value::Nil),
+            _ => Err(self.error("Expect a value")),
+        }
+    }
+
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<Value, String>) -> Result<Value, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(String::from("JSON is nested too deeply."));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Value, String> {
+        self.advance();
+        let mut map = Map::new();
+        self.skip_whitespace();
+        if self.matches('}') {
+            return Ok(Value::Map(Rc::new(RefCell::new(map))));
+        }
+
+        loop {
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'"') {
+                return Err(self.error("Expect a string key"));
+            }
+            let key = Value::LoxString(Rc::from(self.string()?));
+            self.skip_whitespace();
+            if !self.matches(':') {
+                return Err(self.error("Expect ':' after key"));
+            }
+            let value = self.value()?;
+            map.insert(key, value)?;
+
+            self.skip_whitespace();
+            if self.matches('}') {
+                return Ok(Value::Map(Rc::new(RefCell::new(map))));
+            }
+            if !self.matches(',') {
+                return Err(self.error("Expect ',' or '}' after value"));
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.advance();
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.matches(']') {
+            return Ok(Value::List(Rc::new(RefCell::new(items))));
+        }
+
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            if self.matches(']') {
+                return Ok(Value::List(Rc::new(RefCell::new(items))));
+            }
+            if !self.matches(',') {
+                return Err(self.error("Expect ',' or ']' after value"));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.advance();
+        let mut string = String::new();
+        loop {
+            match self.advance() {
+                Some('"') => return Ok(string),
+                Some('\\') => string.push(self.escape()?),
+                Some(c) if c < ' ' => {
+                    return Err(self.error("Unescaped control character in string"))
+                }
+                Some(c) => string.push(c),
+                None => return Err(self.error("Unterminated string")),
+            }
+        }
+    }
+
+    fn escape(&mut self) -> Result<char, String> {
+        let c = match self.advance() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('/') => '/',
+            Some('b') => '\u{8}',
+            Some('f') => '\u{c}',
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('u') => return self.unicode_escape(),
+            _ => return Err(self.error("Invalid escape")),
+        };
+        Ok(c)
+    }
+
+    // characters outside the basic plane are written as a surrogate pair of escapes
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !(self.matches('\\') && self.matches('u')) {
+                return Err(self.error("Expect a low surrogate escape"));
+            }
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err(self.error("Invalid low surrogate"));
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        std::char::from_u32(code).ok_or_else(|| self.error("Invalid unicode escape"))
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self.advance().and_then(|c| c.to_digit(16));
+            match digit {
+                Some(digit) => code = code * 16 + digit,
+                None => return Err(self.error("Expect four hex digits")),
+            }
+        }
+        Ok(code)
+    }
+
+    // follows the JSON grammar, which is stricter than what f64 parsing accepts
+    fn number(&mut self) -> Result<Value, String> {
+        let mut number = String::new();
+        if self.matches('-') {
+            number.push('-');
+        }
+        if self.matches('0') {
+            number.push('0');
+        } else if !self.digits(&mut number) {
+            return Err(self.error("Expect a digit"));
+        }
+        if self.matches('.') {
+            number.push('.');
+            if !self.digits(&mut number) {
+                return Err(self.error("Expect a digit after '.'"));
+            }
+        }
+        if let Some(&e) = self.chars.peek().filter(|&&c| c == 'e' || c == 'E') {
+            self.advance();
+            number.push(e);
+            if let Some(&sign) = self.chars.peek().filter(|&&c| c == '+' || c == '-') {
+                self.advance();
+                number.push(sign);
+            }
+            if !self.digits(&mut number) {
+                return Err(self.error("Expect a digit in exponent"));
+            }
+        }
+
+        number
+            .parse()
+            .map(Value::Number)
+            .map_err(|_| self.error("Invalid number"))
+    }
+
+    /// False when there wasn't at least one digit
+    fn digits(&mut self, number: &mut String) -> bool {
+        let start = number.len();
+        while let Some(&c) = self.chars.peek().filter(|c| c.is_ascii_digit()) {
+            self.advance();
+            number.push(c);
+        }
+        number.len() > start
+    }
+
+    fn literal(&mut self, word: &str, value: Value) -> Result<Value, String> {
+        for expected in word.chars() {
+            if !self.matches(expected) {
+                return Err(self.error("Expect a value"));
+            }
+        }
+        Ok(value)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(' ') | Some('\t') | Some('\n') | Some('\r') = self.chars.peek() {
+            self.advance();
+        }
+    }
+
+    fn matches(&mut self, expected: char) -> bool {
+        if self.chars.peek() == Some(&expected) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if c.is_some() {
+            self.position += 1;
+        }
+        c
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("Invalid JSON at character {}: {}.", self.position, message)
+    }
+}
+
+struct Writer {
+    output: String,
+    // spaces per level, 0 writes everything on one line
+    indent: usize,
+    // lists and maps being written, to catch cycles
+    parents: Vec<usize>,
+}
+
+impl Writer {
+    fn value(&mut self, value: &Value) -> Result<(), String> {
+        match value {
+            Value::Nil => self.output.push_str("null"),
+            Value::Bool(value) => write!(self.output, "{}", value).unwrap(),
+            Value::Number(value) if value.is_finite() => write!(self.output, "{}", value).unwrap(),
+            Value::Number(value) => return Err(format!("Can't convert {} to JSON.", value)),
+            Value::LoxString(value) => self.string(value),
+            Value::List(list) => {
+                self.enter(Rc::as_ptr(list) as usize)?;
+                let list = list.borrow();
+                self.output.push('[');
+                for (i, item) in list.iter().enumerate() {
+                    self.separator(i);
+                    self.value(item)?;
+                }
+                self.close(list.len(), ']');
+            }
+            Value::Map(map) => {
+                self.enter(Rc::as_ptr(map) as usize)?;
+                let map = map.borrow();
+                self.output.push('{');
+                for (i, (key, value)) in map.iter().enumerate() {
+                    let key = match key {
+                        Value::LoxString(key) => key,
+                        key => {
+                            return Err(format!(
+                                "Can't convert map key {} to JSON, keys must be strings.",
+                                key.repr()
+                            ))
+                        }
+                    };
+                    self.separator(i);
+                    self.string(key);
+                    self.output.push(':');
+                    if self.indent > 0 {
+                        self.output.push(' ');
+                    }
+                    self.value(value)?;
+                }
+                self.close(map.len(), '}');
+            }
+            value => return Err(format!("Can't convert {} to JSON.", value)),
+        }
+        Ok(())
+    }
+
+    fn enter(&mut self, address: usize) -> Result<(), String> {
+        if self.parents.contains(&address) {
+            return Err(String::from(
+                "Can't convert a value that contains itself to JSON.",
+            ));
+        }
+        self.parents.push(address);
+        Ok(())
+    }
+
+    /// Written before the item at index in a list or map
+    fn separator(&mut self, index: usize) {
+        if index > 0 {
+            self.output.push(',');
+        }
+        self.newline(self.parents.len());
+    }
+
+    fn close(&mut self, len: usize, bracket: char) {
+        self.parents.pop();
+        if len > 0 {
+            self.newline(self.parents.len());
+        }
+        self.output.push(bracket);
+    }
+
+    fn newline(&mut self, depth: usize) {
+        if self.indent > 0 {
+            self.output.push('\n');
+            self.output.push_str(&" ".repeat(self.indent * depth));
+        }
+    }
+
+    fn string(&mut self, string: &str) {
+        self.output.push('"');
+        for c in string.chars() {
+            match c {
+                '"' => self.output.push_str("\\\""),
+                '\\' => self.output.push_str("\\\\"),
+                '\n' => self.output.push_str("\\n"),
+                '\r' => self.output.push_str("\\r"),
+                '\t' => self.output.push_str("\\t"),
+                c if c < ' ' => write!(self.output, "\\u{:04x}", c as u32).unwrap(),
+                c => self.output.push(c),
+            }
+        }
+        self.output.push('"');
+    }
+}
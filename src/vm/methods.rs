@@ -0,0 +1,165 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::object::{LoxIterator, Map};
+use crate::value::Value;
+
+/// Calls a built in method on a list or map, None when the receiver has no methods
+pub(super) fn call_method(
+    receiver: &Value,
+    name: &str,
+    args: &[Value],
+) -> Option<Result<Value, String>> {
+    match receiver {
+        Value::List(_) | Value::Map(_) | Value::LoxString(_) | Value::Range(_)
+            if name == "iter" =>
+        {
+            Some(check_arity(args, 0).map(|_| iterator_value(receiver)))
+        }
+        Value::List(list) => Some(list_method(list, name, args)),
+        Value::Map(map) => Some(map_method(map, name, args)),
+        Value::LoxString(_) | Value::Range(_) => {
+            Some(Err(format!("Undefined property '{}'.", name)))
+        }
+        Value::Iterator(iterator) => Some(iterator_method(iterator, name, args)),
+        _ => None,
+    }
+}
+
+/// The iterator a for-in loop uses for value, None when only an iter() method can make one
+pub(super) fn iterator(value: &Value) -> Option<LoxIterator> {
+    let iterator = match value {
+        Value::List(list) => LoxIterator::List {
+            list: list.clone(),
+            index: 0,
+        },
+        Value::Map(map) => LoxIterator::Values {
+            values: map.borrow().iter().map(|(key, _)| key.clone()).collect(),
+            index: 0,
+        },
+        Value::LoxString(string) => LoxIterator::Values {
+            values: string
+                .chars()
+                .map(|c| Value::LoxString(Rc::from(c.to_string())))
+                .collect(),
+            index: 0,
+        },
+        Value::Range(range) => LoxIterator::Range {
+            next: range.start,
+            range: *range,
+        },
+        _ => return None,
+    };
+    Some(iterator)
+}
+
+fn iterator_value(value: &Value) -> Value {
+    let iterator = iterator(value).expect("Expected an iterable value");
+    Value::Iterator(Rc::new(RefCell::new(iterator)))
+}
+
+fn iterator_method(
+    iterator: &RefCell<LoxIterator>,
+    name: &str,
+    args: &[Value],
+) -> Result<Value, String> {
+    match name {
+        // nil once it runs out, which for-in loops over built in iterators don't rely on
+        "next" => {
+            check_arity(args, 0)?;
+            Ok(iterator.borrow_mut().next().unwrap_or(Value::Nil))
+        }
+        _ => Err(format!("Undefined property '{}'.", name)),
+    }
+}
+
+fn list_method(list: &RefCell<Vec<Value>>, name: &str, args: &[Value]) -> Result<Value, String> {
+    match name {
+        "len" => {
+            check_arity(args, 0)?;
+            Ok(Value::Number(list.borrow().len() as f64))
+        }
+        "push" => {
+            check_arity(args, 1)?;
+            list.borrow_mut().push(args[0].clone());
+            Ok(Value::Nil)
+        }
+        "pop" => {
+            check_arity(args, 0)?;
+            list.borrow_mut()
+                .pop()
+                .ok_or_else(|| String::from("Can't pop from an empty list."))
+        }
+        "slice" => {
+            if args.is_empty() || args.len() > 2 {
+                return Err(format!("Expected 1 to 2 arguments but got {}.", args.len()));
+            }
+            let list = list.borrow();
+            let start = slice_bound(list.len(), &args[0])?;
+            let end = match args.get(1) {
+                Some(end) => slice_bound(list.len(), end)?,
+                None => list.len(),
+            };
+            Ok(list_value(list[start..end.max(start)].to_vec()))
+        }
+        _ => Err(format!("Undefined property '{}'.", name)),
+    }
+}
+
+fn map_method(map: &RefCell<Map>, name: &str, args: &[Value]) -> Result<Value, String> {
+    match name {
+        "len" => {
+            check_arity(args, 0)?;
+            Ok(Value::Number(map.borrow().len() as f64))
+        }
+        "keys" => {
+            check_arity(args, 0)?;
+            let keys = map.borrow().iter().map(|(key, _)| key.clone()).collect();
+            Ok(list_value(keys))
+        }
+        "values" => {
+            check_arity(args, 0)?;
+            let values = map
+                .borrow()
+                .iter()
+                .map(|(_, value)| value.clone())
+                .collect();
+            Ok(list_value(values))
+        }
+        "has" => {
+            check_arity(args, 1)?;
+            Ok(Value::Bool(map.borrow().get(&args[0])?.is_some()))
+        }
+        "remove" => {
+            check_arity(args, 1)?;
+            Ok(map.borrow_mut().remove(&args[0])?.unwrap_or(Value::Nil))
+        }
+        _ => Err(format!("Undefined property '{}'.", name)),
+    }
+}
+
+fn check_arity(args: &[Value], arity: usize) -> Result<(), String> {
+    if args.len() == arity {
+        Ok(())
+    } else {
+        Err(format!(
+            "Expected {} arguments but got {}.",
+            arity,
+            args.len()
+        ))
+    }
+}
+
+// like a list index, but one past the end is allowed
+fn slice_bound(len: usize, bound: &Value) -> Result<usize, String> {
+    match bound {
+        Value::Number(bound) if *bound >= 0.0 && bound.fract() == 0.0 => {
+            Ok((*bound as usize).min(len))
+        }
+        _ => Err(String::from("Slice bounds must be non-negative integers.")),
+    }
+}
+
+fn list_value(values: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(values)))
+}
@@ -0,0 +1,1643 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use log::error;
+
+pub mod error;
+mod json;
+mod methods;
+mod modules;
+mod natives;
+#[cfg(test)]
+mod tests;
+
+use crate::chunk::OpCode;
+use crate::compiler;
+use crate::object::*;
+use crate::value::Value;
+use error::InterpretError;
+pub use modules::{FileLoader, ModuleLoader};
+pub use natives::NativeContext;
+
+// frames live in a growable vec, the limit only stops runaway recursion
+const FRAMES_MAX: usize = 4096;
+
+// runtime errors are instances of Error, scripts can throw and subclass it too
+const PRELUDE: &str = "class Error { init(message) { this.message = message; } }";
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // stack index of the frame's slot zero, which holds the callee or receiver
+    slots: usize,
+    // parameters the call left for their defaults, empty when every one was passed
+    missing: Vec<bool>,
+    // the top level of an imported module, returning from it hands back the module
+    is_module: bool,
+    // the generator this frame runs, and whether a for-in loop rather than next() resumed it
+    generator: Option<(Rc<RefCell<Generator>>, bool)>,
+}
+
+// Where execution continues when a value is thrown inside a try block
+struct Handler {
+    // frame count when the handler was pushed, frames above it are unwound
+    frame_depth: usize,
+    stack_len: usize,
+    catch_ip: usize,
+}
+
+pub struct Vm<W: Write> {
+    frames: Vec<CallFrame>,
+    stack: Vec<Value>,
+    // natives and the prelude, visible from every module unless it defines the name itself
+    builtins: HashMap<Rc<str>, Value>,
+    main_module: Rc<Module>,
+    // imported modules by the path they were loaded from
+    modules: HashMap<PathBuf, Rc<Module>>,
+    // modules whose top level is running, with the frame count once it started
+    loading: Vec<(Rc<Module>, usize)>,
+    module_loader: Box<dyn ModuleLoader>,
+    search_paths: Vec<PathBuf>,
+    // upvalues still pointing into the stack, ordered by slot
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    init_string: Rc<str>,
+    native_context: NativeContext,
+    handlers: Vec<Handler>,
+    error_class: Rc<RefCell<Class>>,
+    // the value being thrown and the stack trace where it was thrown
+    thrown: Option<Value>,
+    trace: Vec<String>,
+    // bodies of the test blocks run so far, None unless the vm is running tests
+    tests: Option<Vec<(Rc<str>, Rc<Closure>)>>,
+    out: W,
+}
+
+impl<W: Write> Vm<W> {
+    /// `print` statements write to out
+    pub fn new(out: W) -> Self {
+        let mut vm = Self {
+            frames: Vec::new(),
+            stack: Vec::new(),
+            builtins: HashMap::new(),
+            main_module: Rc::new(Module::new(Rc::from("main"), None)),
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            module_loader: Box::new(FileLoader),
+            search_paths: Vec::new(),
+            open_upvalues: Vec::new(),
+            init_string: Rc::from("init"),
+            native_context: NativeContext::new(),
+            handlers: Vec::new(),
+            error_class: Rc::new(RefCell::new(Class::new(Rc::from("Error")))),
+            thrown: None,
+            trace: Vec::new(),
+            tests: None,
+            out,
+        };
+
+        natives::define_natives(&mut vm);
+        vm.interpret(String::from(PRELUDE))
+            .expect("Failed to run prelude");
+        let error_class = vm.main_module.globals.borrow_mut().remove("Error");
+        if let Some(Value::Class(error_class)) = error_class {
+            vm.error_class = error_class.clone();
+            vm.builtins
+                .insert(Rc::from("Error"), Value::Class(error_class));
+        }
+        vm
+    }
+
+    /// Imports in the main script are looked up next to path, call this before interpret
+    pub fn set_script_path(&mut self, path: &Path) {
+        let name = Rc::from(path.to_string_lossy().as_ref());
+        self.main_module = Rc::new(Module::new(name, Some(path.to_path_buf())));
+    }
+
+    /// Directory searched for imports not found next to the importing file, in the order added
+    pub fn add_search_path(&mut self, path: PathBuf) {
+        self.search_paths.push(path);
+    }
+
+    /// Makes test blocks keep their bodies for run_tests instead of skipping them, call this
+    /// before interpret
+    pub fn enable_tests(&mut self) {
+        self.tests = Some(Vec::new());
+    }
+
+    /// Runs the tests whose names contain filter in the order they were declared, failures have
+    /// already been reported when this returns
+    pub fn run_tests(&mut self, filter: &str) -> Vec<(Rc<str>, Result<(), InterpretError>)> {
+        let tests = self.tests.take().unwrap_or_default();
+        let mut results = Vec::new();
+        for (name, test) in tests {
+            if !name.contains(filter) {
+                continue;
+            }
+
+            self.stack.push(Value::Closure(test.clone()));
+            let result = match self.call(test, 0) {
+                Ok(()) => self.run(),
+                Err(error) => Err(self.abort(error)),
+            };
+            let exited = matches!(result, Err(InterpretError::Exit(_)));
+            results.push((name, result));
+            if exited {
+                break;
+            }
+        }
+        results
+    }
+
+    /// What args() returns to the script
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.native_context.args = args.into_iter().map(Rc::from).collect();
+    }
+
+    /// Makes the file, stdin, environment and exit natives fail, imports still use the module loader
+    pub fn disable_io(&mut self) {
+        self.native_context.io_enabled = false;
+    }
+
+    pub fn set_module_loader(&mut self, loader: impl ModuleLoader + 'static) {
+        self.module_loader = Box::new(loader);
+    }
+
+    /// Compiles and runs source, globals persist between calls
+    pub fn interpret(&mut self, source: String) -> Result<(), InterpretError> {
+        let script = compiler::compile(source).map_err(InterpretError::CompileError)?;
+        self.run_script(script)
+    }
+
+    /// Runs a compiled top level script to completion
+    pub fn run_script(&mut self, script: Function) -> Result<(), InterpretError> {
+        let script = Rc::new(Closure::new(Rc::new(script), self.main_module.clone()));
+        self.stack.push(Value::Closure(script.clone()));
+        if let Err(error) = self.call(script, 0) {
+            return Err(self.abort(error));
+        }
+        self.run()
+    }
+
+    fn run(&mut self) -> Result<(), InterpretError> {
+        loop {
+            match self.execute() {
+                Ok(()) => return Ok(()),
+                Err(InterpretError::Exit(code)) => {
+                    self.reset();
+                    return Err(InterpretError::Exit(code));
+                }
+                Err(error) => {
+                    if !self.catch() {
+                        return Err(self.abort(error));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs until the script returns or something is thrown
+    fn execute(&mut self) -> Result<(), InterpretError> {
+        loop {
+            let instruction = self.read_byte();
+            let op = match OpCode::try_from(instruction) {
+                Ok(op) => op,
+                Err(byte) => return Err(self.runtime_error(&format!("Unknown opcode {}.", byte))),
+            };
+
+            match op {
+                OpCode::Constant => {
+                    let constant = self.read_constant();
+                    self.stack.push(constant);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_string();
+                    let value = self
+                        .frame()
+                        .closure
+                        .module
+                        .globals
+                        .borrow()
+                        .get(&name)
+                        .cloned();
+                    match value.or_else(|| self.builtins.get(&name).cloned()) {
+                        Some(value) => self.stack.push(value),
+                        None => {
+                            return Err(
+                                self.runtime_error(&format!("Undefined variable '{}'.", name))
+                            )
+                        }
+                    }
+                }
+                OpCode::DefineGlobal | OpCode::DefineConstant => {
+                    let name = self.read_string();
+                    let module = self.frame().closure.module.clone();
+                    if module.constants.borrow().contains(&name) {
+                        return Err(
+                            self.runtime_error(&format!("Can't redefine constant '{}'.", name))
+                        );
+                    }
+
+                    let value = self.pop();
+                    if op == OpCode::DefineConstant {
+                        module.constants.borrow_mut().insert(name.clone());
+                    }
+                    module.globals.borrow_mut().insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_string();
+                    let value = self.peek(0).clone();
+                    let module = self.frame().closure.module.clone();
+                    // assignments the compiler couldn't see, like from a later repl line
+                    if module.constants.borrow().contains(&name) {
+                        return Err(
+                            self.runtime_error(&format!("Can't assign to constant '{}'.", name))
+                        );
+                    }
+                    let mut globals = module.globals.borrow_mut();
+                    match globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => {
+                            return Err(
+                                self.runtime_error(&format!("Undefined variable '{}'.", name))
+                            )
+                        }
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let slot = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[slot].clone();
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(index) => self.stack[*index].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let slot = self.read_byte() as usize;
+                    let upvalue = self.frame().closure.upvalues[slot].clone();
+                    let value = self.peek(0).clone();
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(index) => self.stack[*index] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    };
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_string();
+                    let instance = match self.peek(0) {
+                        Value::Instance(instance) => instance.clone(),
+                        Value::Module(module) => {
+                            let value = self.module_variable(&module.clone(), &name)?;
+                            self.pop();
+                            self.stack.push(value);
+                            continue;
+                        }
+                        Value::Enum(enum_) => {
+                            let variant = match enum_.variant(&name) {
+                                Some(variant) => variant,
+                                None => {
+                                    return Err(self
+                                        .runtime_error(&format!("Undefined property '{}'.", name)))
+                                }
+                            };
+                            self.pop();
+                            self.stack.push(variant);
+                            continue;
+                        }
+                        Value::EnumValue(value) => {
+                            let fields = &value.variant.fields;
+                            let field = match fields.iter().position(|field| *field == name) {
+                                Some(index) => value.values[index].clone(),
+                                None => {
+                                    return Err(self
+                                        .runtime_error(&format!("Undefined property '{}'.", name)))
+                                }
+                            };
+                            self.pop();
+                            self.stack.push(field);
+                            continue;
+                        }
+                        Value::Class(class) => {
+                            let method = class.borrow().class_methods.get(&name).cloned();
+                            match method {
+                                Some(method) => self.bind(method),
+                                None => {
+                                    return Err(self
+                                        .runtime_error(&format!("Undefined property '{}'.", name)))
+                                }
+                            }
+                            continue;
+                        }
+                        _ => return Err(self.runtime_error("Only instances have properties.")),
+                    };
+
+                    let field = instance.borrow().fields.get(&name).cloned();
+                    match field {
+                        Some(value) => {
+                            self.pop();
+                            self.stack.push(value);
+                        }
+                        None => {
+                            let class = instance.borrow().class.clone();
+                            self.bind_method(&class, &name)?;
+                        }
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_string();
+                    let instance = match self.peek(1) {
+                        Value::Instance(instance) => instance.clone(),
+                        _ => return Err(self.runtime_error("Only instances have fields.")),
+                    };
+
+                    let value = self.pop();
+                    instance.borrow_mut().fields.insert(name, value.clone());
+                    self.pop();
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_string();
+                    if let Value::Class(superclass) = self.pop() {
+                        self.bind_method(&superclass, &name)?;
+                    }
+                }
+                OpCode::GetIndex => {
+                    if self.overload("__index__", 1)? {
+                        continue;
+                    }
+                    let index = self.pop();
+                    let container = self.pop();
+                    let value = self.get_index(&container, &index)?;
+                    self.stack.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let container = self.pop();
+                    self.set_index(&container, index, value.clone())?;
+                    self.stack.push(value);
+                }
+                OpCode::Equal => {
+                    if self.overload_binary("__eq__", Some("__eq__"))? {
+                        continue;
+                    }
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.stack.push(Value::Bool(a == b));
+                }
+                OpCode::Greater => {
+                    if !self.overload_binary("__gt__", Some("__lt__"))? {
+                        self.binary_number_op(|a, b| Value::Bool(a > b))?
+                    }
+                }
+                OpCode::Less => {
+                    if !self.overload_binary("__lt__", Some("__gt__"))? {
+                        self.binary_number_op(|a, b| Value::Bool(a < b))?
+                    }
+                }
+                OpCode::Add => {
+                    if self.overload_binary("__add__", None)? {
+                        continue;
+                    }
+                    let result = match (self.peek(1), self.peek(0)) {
+                        (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+                        (Value::LoxString(a), Value::LoxString(b)) => {
+                            Value::LoxString(format!("{}{}", a, b).into())
+                        }
+                        _ => {
+                            return Err(
+                                self.runtime_error("Operands must be two numbers or two strings.")
+                            )
+                        }
+                    };
+
+                    self.pop();
+                    self.pop();
+                    self.stack.push(result);
+                }
+                OpCode::Subtract => {
+                    if !self.overload_binary("__sub__", None)? {
+                        self.binary_number_op(|a, b| Value::Number(a - b))?
+                    }
+                }
+                OpCode::Multiply => {
+                    if !self.overload_binary("__mul__", None)? {
+                        self.binary_number_op(|a, b| Value::Number(a * b))?
+                    }
+                }
+                OpCode::Divide => {
+                    if !self.overload_binary("__div__", None)? {
+                        self.binary_number_op(|a, b| Value::Number(a / b))?
+                    }
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(value.is_falsey()));
+                }
+                OpCode::Negate => match self.peek(0) {
+                    Value::Number(value) => {
+                        let negated = Value::Number(-value);
+                        self.pop();
+                        self.stack.push(negated);
+                    }
+                    _ => {
+                        if !self.overload("__neg__", 0)? {
+                            return Err(self.runtime_error("Operand must be a number."));
+                        }
+                    }
+                },
+                OpCode::Print => {
+                    let to_string = self
+                        .special_method("__str__", 0)
+                        .or_else(|| self.special_method("toString", 0));
+                    if let Some(method) = to_string {
+                        // print runs again once the method returns, with the string in the
+                        // instance's place
+                        self.frame_mut().ip -= 1;
+                        self.call(method, 0)?;
+                        continue;
+                    }
+                    let value = self.pop();
+                    writeln!(self.out, "{}", value).expect("Failed to write output");
+                }
+                OpCode::Jump => {
+                    let offset = self.read_short() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short() as usize;
+                    if self.peek(0).is_falsey() {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_short() as usize;
+                    self.frame_mut().ip -= offset;
+                }
+                OpCode::JumpIfPassed => {
+                    let parameter = self.read_byte() as usize;
+                    let offset = self.read_short() as usize;
+                    let frame = self.frame_mut();
+                    if !frame.missing.get(parameter).copied().unwrap_or(false) {
+                        frame.ip += offset;
+                    }
+                }
+                OpCode::Call => {
+                    let arg_count = self.read_byte() as usize;
+                    let callee = self.peek(arg_count).clone();
+                    self.call_value(callee, arg_count)?;
+                }
+                OpCode::CallNamed => {
+                    let positional = self.read_byte() as usize;
+                    let named = self.read_byte() as usize;
+                    let callee = self.peek(positional + named * 2).clone();
+                    self.call_named(callee, positional, named)?;
+                }
+                OpCode::Invoke => {
+                    let name = self.read_string();
+                    let arg_count = self.read_byte() as usize;
+                    self.invoke(&name, arg_count)?;
+                }
+                OpCode::SuperInvoke => {
+                    let name = self.read_string();
+                    let arg_count = self.read_byte() as usize;
+                    if let Value::Class(superclass) = self.pop() {
+                        self.invoke_from_class(&superclass, &name, arg_count)?;
+                    }
+                }
+                OpCode::Closure => {
+                    let function = match self.read_constant() {
+                        Value::Function(function) => function,
+                        constant => panic!("Expected function constant, found {}", constant),
+                    };
+
+                    let mut closure = Closure::new(function, self.frame().closure.module.clone());
+                    for _ in 0..closure.function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let upvalue = if is_local {
+                            self.capture_upvalue(self.frame().slots + index)
+                        } else {
+                            self.frame().closure.upvalues[index].clone()
+                        };
+                        closure.upvalues.push(upvalue);
+                    }
+                    self.stack.push(Value::Closure(Rc::new(closure)));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let mut result = self.pop();
+                    let frame = self.frames.pop().unwrap();
+                    if let Some((generator, _)) = &frame.generator {
+                        // a finished generator gives nil, what it returns is dropped
+                        generator.borrow_mut().state = GeneratorState::Done;
+                        result = Value::Nil;
+                    }
+                    if frame.is_module {
+                        let (module, _) = self.loading.pop().unwrap();
+                        if let Some(path) = &module.path {
+                            self.modules.insert(path.clone(), module.clone());
+                        }
+                        result = Value::Module(module);
+                    }
+                    self.close_upvalues(frame.slots);
+                    let depth = self.frames.len();
+                    self.handlers.retain(|handler| handler.frame_depth <= depth);
+                    if self.frames.is_empty() {
+                        // pop the script function itself
+                        self.stack.clear();
+                        return Ok(());
+                    }
+
+                    self.stack.truncate(frame.slots);
+                    self.stack.push(result);
+                }
+                OpCode::Class => {
+                    let name = self.read_string();
+                    let class = Class::new(name);
+                    self.stack.push(Value::Class(Rc::new(RefCell::new(class))));
+                }
+                OpCode::Inherit => {
+                    let superclass = match self.peek(1) {
+                        Value::Class(superclass) => superclass.clone(),
+                        _ => return Err(self.runtime_error("Superclass must be a class.")),
+                    };
+
+                    if let Value::Class(subclass) = self.peek(0) {
+                        let mut subclass = subclass.borrow_mut();
+                        subclass.copy_methods(&superclass.borrow());
+                        subclass.superclass = Some(superclass);
+                    }
+                    self.pop();
+                }
+                OpCode::Method | OpCode::ClassMethod | OpCode::Getter => {
+                    let name = self.read_string();
+                    if let (Value::Class(class) | Value::Trait(class), Value::Closure(method)) =
+                        (self.peek(1), self.peek(0))
+                    {
+                        let mut class = class.borrow_mut();
+                        let methods = match op {
+                            OpCode::Method => &mut class.methods,
+                            OpCode::ClassMethod => &mut class.class_methods,
+                            _ => &mut class.getters,
+                        };
+                        methods.insert(name, method.clone());
+                    }
+                    self.pop();
+                }
+                OpCode::Trait => {
+                    let name = self.read_string();
+                    let methods = Class::new(name);
+                    self.stack
+                        .push(Value::Trait(Rc::new(RefCell::new(methods))));
+                }
+                OpCode::UseTrait => {
+                    let methods = match self.peek(0) {
+                        Value::Trait(methods) => methods.clone(),
+                        _ => return Err(self.runtime_error("Only traits can follow 'with'.")),
+                    };
+
+                    if let Value::Class(class) = self.peek(1) {
+                        class.borrow_mut().copy_methods(&methods.borrow());
+                    }
+                    self.pop();
+                }
+                OpCode::Import => {
+                    let path = self.read_string();
+                    self.import(&path)?;
+                }
+                OpCode::PushHandler => {
+                    let offset = self.read_short() as usize;
+                    self.handlers.push(Handler {
+                        frame_depth: self.frames.len(),
+                        stack_len: self.stack.len(),
+                        catch_ip: self.frame().ip + offset,
+                    });
+                }
+                OpCode::PopHandler => {
+                    self.handlers.pop();
+                }
+                OpCode::Throw => {
+                    let value = self.pop();
+                    return Err(self.throw(value));
+                }
+                OpCode::AssertFail => {
+                    let condition = self.read_string();
+                    let message = match self.pop() {
+                        Value::Nil => format!("Assertion failed: {}.", condition),
+                        message => format!("Assertion failed: {}: {}", condition, message),
+                    };
+                    return Err(self.runtime_error(&message));
+                }
+                OpCode::DefineTest => {
+                    let name = self.read_string();
+                    let test = self.pop();
+                    if let (Some(tests), Value::Closure(test)) = (&mut self.tests, test) {
+                        tests.push((name, test));
+                    }
+                }
+                OpCode::BuildList => {
+                    let element_count = self.read_byte() as usize;
+                    let elements = self.stack.split_off(self.stack.len() - element_count);
+                    self.stack
+                        .push(Value::List(Rc::new(RefCell::new(elements))));
+                }
+                OpCode::BuildMap => {
+                    let entry_count = self.read_byte() as usize;
+                    let entries = self.stack.split_off(self.stack.len() - entry_count * 2);
+                    let mut map = Map::new();
+                    for entry in entries.chunks(2) {
+                        if let Err(message) = map.insert(entry[0].clone(), entry[1].clone()) {
+                            return Err(self.runtime_error(&message));
+                        }
+                    }
+                    self.stack.push(Value::Map(Rc::new(RefCell::new(map))));
+                }
+                OpCode::MatchRange => {
+                    let inclusive = self.read_byte() == 1;
+                    let end = self.pop();
+                    let start = self.pop();
+                    let matched = match (self.pop(), start, end) {
+                        (Value::Number(value), Value::Number(start), Value::Number(end)) => {
+                            start <= value && (value < end || inclusive && value == end)
+                        }
+                        _ => false,
+                    };
+                    self.stack.push(Value::Bool(matched));
+                }
+                OpCode::MatchInstance => {
+                    let class = match self.pop() {
+                        Value::Class(class) => class,
+                        _ => return Err(self.runtime_error("Only classes can be matched on.")),
+                    };
+                    let matched = match self.pop() {
+                        Value::Instance(instance) => is_subclass(&instance.borrow().class, &class),
+                        _ => false,
+                    };
+                    self.stack.push(Value::Bool(matched));
+                }
+                OpCode::HasField => {
+                    let name = self.read_string();
+                    let matched = match self.pop() {
+                        Value::Instance(instance) => instance.borrow().fields.contains_key(&name),
+                        _ => false,
+                    };
+                    self.stack.push(Value::Bool(matched));
+                }
+                OpCode::MatchList => {
+                    let length = self.read_byte() as usize;
+                    let has_rest = self.read_byte() == 1;
+                    let matched = match self.pop() {
+                        Value::List(list) => {
+                            let actual = list.borrow().len();
+                            actual == length || has_rest && actual > length
+                        }
+                        _ => false,
+                    };
+                    self.stack.push(Value::Bool(matched));
+                }
+                OpCode::BuildRange => {
+                    let inclusive = self.read_byte() == 1;
+                    let range = match (self.peek(1), self.peek(0)) {
+                        (Value::Number(start), Value::Number(end)) => Range {
+                            start: *start,
+                            end: *end,
+                            inclusive,
+                        },
+                        _ => return Err(self.runtime_error("Range bounds must be numbers.")),
+                    };
+                    self.pop();
+                    self.pop();
+                    self.stack.push(Value::Range(range));
+                }
+                OpCode::GetIter => {
+                    let value = self.peek(0).clone();
+                    match value {
+                        Value::Iterator(_) | Value::Generator(_) => {}
+                        Value::Instance(_) => self.invoke(&Rc::from("iter"), 0)?,
+                        value => {
+                            match methods::iterator(&value) {
+                                Some(iterator) => {
+                                    self.pop();
+                                    let iterator = Rc::new(RefCell::new(iterator));
+                                    self.stack.push(Value::Iterator(iterator));
+                                }
+                                None => return Err(self.runtime_error(
+                                    "Only lists, maps, strings, ranges and objects with an iter() \
+                                     method can be iterated.",
+                                )),
+                            }
+                        }
+                    }
+                }
+                OpCode::IterNext => {
+                    let slot = self.read_byte() as usize;
+                    let offset = self.read_short() as usize;
+                    let iterator = self.stack[self.frame().slots + slot].clone();
+                    match iterator {
+                        // a built in iterator skips the IterCheck after this, so nil is a value
+                        Value::Iterator(iterator) => match iterator.borrow_mut().next() {
+                            Some(value) => {
+                                self.stack.push(value);
+                                self.frame_mut().ip += 3;
+                            }
+                            None => self.frame_mut().ip += offset,
+                        },
+                        Value::Generator(generator) => self.resume(generator, true)?,
+                        // otherwise next() returns nil when it is done, which IterCheck looks for
+                        iterator => {
+                            self.stack.push(iterator);
+                            self.invoke(&Rc::from("next"), 0)?;
+                        }
+                    }
+                }
+                OpCode::IterCheck => {
+                    let offset = self.read_short() as usize;
+                    if let Value::Nil = self.peek(0) {
+                        self.pop();
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Yield => {
+                    let value = self.pop();
+                    let CallFrame {
+                        ip,
+                        slots,
+                        missing,
+                        generator,
+                        ..
+                    } = self.frames.pop().unwrap();
+                    let (generator, for_in) = generator.expect("Only generator frames can yield");
+                    // closures made in the generator keep the values captured up to here
+                    self.close_upvalues(slots);
+
+                    // the generator's own try blocks are the last handlers, they pause with it
+                    let depth = self.frames.len();
+                    let first = self
+                        .handlers
+                        .iter()
+                        .position(|handler| handler.frame_depth > depth)
+                        .unwrap_or(self.handlers.len());
+                    let handlers = self.handlers.split_off(first);
+
+                    let mut generator = generator.borrow_mut();
+                    generator.handlers = handlers
+                        .into_iter()
+                        .map(|handler| (handler.stack_len - slots, handler.catch_ip))
+                        .collect();
+                    generator.stack = self.stack.split_off(slots);
+                    generator.ip = ip;
+                    generator.missing = missing;
+                    generator.state = GeneratorState::Suspended;
+
+                    self.stack.push(value);
+                    if for_in {
+                        // skip the IterCheck, so yielding nil doesn't end the loop
+                        self.frame_mut().ip += 3;
+                    }
+                }
+                OpCode::MatchVariant => {
+                    let length = self.read_byte() as usize;
+                    let has_values = self.read_byte() == 1;
+                    let variant = match self.pop() {
+                        Value::EnumVariant(variant) => variant,
+                        Value::EnumValue(value) => value.variant.clone(),
+                        _ => {
+                            return Err(self.runtime_error("Only enum variants can be matched on."))
+                        }
+                    };
+                    if has_values && length != variant.fields.len() {
+                        return Err(self.runtime_error(&format!(
+                            "Pattern has {} values but {} has {}.",
+                            length,
+                            variant,
+                            variant.fields.len()
+                        )));
+                    }
+
+                    let matched = match self.pop() {
+                        Value::EnumValue(value) => Rc::ptr_eq(&value.variant, &variant),
+                        _ => false,
+                    };
+                    self.stack.push(Value::Bool(matched));
+                }
+                OpCode::GetVariantValue => {
+                    let index = self.read_byte() as usize;
+                    let value = match self.pop() {
+                        Value::EnumValue(value) => value.values[index].clone(),
+                        value => panic!("Expected enum value, found {}", value),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::NoMatch => {
+                    let value = self.pop();
+                    return Err(self.runtime_error(&format!("No match arm for {}.", value.repr())));
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> Result<(), InterpretError> {
+        match callee {
+            Value::Closure(closure) => self.call(closure, arg_count),
+            Value::Native(native) => {
+                if let Some(arity) = native.arity.filter(|&arity| arity != arg_count) {
+                    return Err(self.runtime_error(&format!(
+                        "Expected {} arguments but got {}.",
+                        arity, arg_count
+                    )));
+                }
+
+                let args_start = self.stack.len() - arg_count;
+                let result = (native.function)(&mut self.native_context, &self.stack[args_start..]);
+                if let Some(code) = self.native_context.exit_code.take() {
+                    return Err(InterpretError::Exit(code));
+                }
+                match result {
+                    Ok(value) => {
+                        self.stack.truncate(args_start - 1);
+                        self.stack.push(value);
+                        Ok(())
+                    }
+                    Err(message) => Err(self.runtime_error(&message)),
+                }
+            }
+            Value::Class(class) => {
+                let slot = self.stack.len() - arg_count - 1;
+                self.stack[slot] =
+                    Value::Instance(Rc::new(RefCell::new(Instance::new(class.clone()))));
+
+                let initializer = class.borrow().methods.get(&self.init_string).cloned();
+                match initializer {
+                    Some(initializer) => self.call(initializer, arg_count),
+                    None if arg_count != 0 => {
+                        Err(self
+                            .runtime_error(&format!("Expected 0 arguments but got {}.", arg_count)))
+                    }
+                    None => Ok(()),
+                }
+            }
+            Value::BoundMethod(bound) => {
+                let slot = self.stack.len() - arg_count - 1;
+                self.stack[slot] = bound.receiver.clone();
+                self.call(bound.method.clone(), arg_count)
+            }
+            Value::EnumVariant(variant) => {
+                if arg_count != variant.fields.len() {
+                    return Err(self.runtime_error(&format!(
+                        "Expected {} arguments but got {}.",
+                        variant.fields.len(),
+                        arg_count
+                    )));
+                }
+
+                let values = self.stack.split_off(self.stack.len() - arg_count);
+                self.pop();
+                let value = EnumValue {
+                    variant: variant.clone(),
+                    values,
+                };
+                self.stack.push(Value::EnumValue(Rc::new(value)));
+                Ok(())
+            }
+            _ => Err(self.runtime_error("Can only call functions and classes.")),
+        }
+    }
+
+    fn call(&mut self, closure: Rc<Closure>, arg_count: usize) -> Result<(), InterpretError> {
+        let function = closure.function.clone();
+        let min_arity = function.arity - function.optional;
+        if arg_count < min_arity || (arg_count > function.arity && !function.has_rest) {
+            return Err(self.runtime_error(&arity_error(&function, arg_count)));
+        }
+
+        let slots = self.stack.len() - arg_count - 1;
+        let rest = if arg_count > function.arity {
+            self.stack.split_off(slots + 1 + function.arity)
+        } else {
+            Vec::new()
+        };
+
+        let mut missing = Vec::new();
+        if arg_count < function.arity {
+            missing = (0..function.arity).map(|i| i >= arg_count).collect();
+            self.stack.resize(slots + 1 + function.arity, Value::Nil);
+        }
+        if function.has_rest {
+            self.stack.push(Value::List(Rc::new(RefCell::new(rest))));
+        }
+
+        if function.is_generator {
+            let stack = self.stack.split_off(slots);
+            let generator = Generator::new(closure, stack, missing);
+            self.stack
+                .push(Value::Generator(Rc::new(RefCell::new(generator))));
+            return Ok(());
+        }
+        self.push_frame(closure, slots, missing)
+    }
+
+    /// Calls with named arguments, each one is a name and value pair after the positional ones
+    fn call_named(
+        &mut self,
+        callee: Value,
+        positional: usize,
+        named: usize,
+    ) -> Result<(), InterpretError> {
+        let slot = self.stack.len() - positional - named * 2 - 1;
+        let closure = match callee {
+            Value::Closure(closure) => closure,
+            Value::BoundMethod(bound) => {
+                self.stack[slot] = bound.receiver.clone();
+                bound.method.clone()
+            }
+            Value::Class(class) => {
+                let initializer = class.borrow().methods.get(&self.init_string).cloned();
+                let initializer = match initializer {
+                    Some(initializer) => initializer,
+                    None => {
+                        return Err(self.runtime_error(&format!(
+                            "Expected 0 arguments but got {}.",
+                            positional + named
+                        )))
+                    }
+                };
+                self.stack[slot] = Value::Instance(Rc::new(RefCell::new(Instance::new(class))));
+                initializer
+            }
+            Value::Native(_) => {
+                return Err(self.runtime_error("Native functions don't take named arguments."))
+            }
+            _ => return Err(self.runtime_error("Can only call functions and classes.")),
+        };
+
+        let function = closure.function.clone();
+        if positional > function.arity && !function.has_rest {
+            return Err(self.runtime_error(&arity_error(&function, positional + named)));
+        }
+
+        let pairs = self.stack.split_off(slot + 1 + positional);
+        let mut positional_args = self.stack.split_off(slot + 1);
+        let rest = if positional > function.arity {
+            positional_args.split_off(function.arity)
+        } else {
+            Vec::new()
+        };
+
+        let mut args: Vec<Option<Value>> = positional_args.into_iter().map(Some).collect();
+        args.resize(function.arity, None);
+        for pair in pairs.chunks(2) {
+            let name = match &pair[0] {
+                Value::LoxString(name) => name,
+                name => panic!("Expected argument name, found {}", name),
+            };
+
+            let index = match function.parameters.iter().position(|p| p == name) {
+                Some(index) => index,
+                None => {
+                    return Err(
+                        self.runtime_error(&format!("Unexpected argument named '{}'.", name))
+                    )
+                }
+            };
+            if args[index].is_some() {
+                return Err(
+                    self.runtime_error(&format!("Argument '{}' was given more than once.", name))
+                );
+            }
+            args[index] = Some(pair[1].clone());
+        }
+
+        let min_arity = function.arity - function.optional;
+        if let Some(index) = args[..min_arity].iter().position(Option::is_none) {
+            return Err(self.runtime_error(&format!(
+                "Missing argument '{}'.",
+                function.parameters[index]
+            )));
+        }
+
+        let missing = args.iter().map(Option::is_none).collect();
+        self.stack
+            .extend(args.into_iter().map(|arg| arg.unwrap_or(Value::Nil)));
+        if function.has_rest {
+            self.stack.push(Value::List(Rc::new(RefCell::new(rest))));
+        }
+
+        self.push_frame(closure, slot, missing)
+    }
+
+    fn push_frame(
+        &mut self,
+        closure: Rc<Closure>,
+        slots: usize,
+        missing: Vec<bool>,
+    ) -> Result<(), InterpretError> {
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.runtime_error("Stack overflow."));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots,
+            missing,
+            is_module: false,
+            generator: None,
+        });
+        Ok(())
+    }
+
+    /// Runs a generator from where it paused, until it yields or finishes it pushes nil
+    fn resume(
+        &mut self,
+        generator: Rc<RefCell<Generator>>,
+        for_in: bool,
+    ) -> Result<(), InterpretError> {
+        let state = generator.borrow().state;
+        match state {
+            GeneratorState::Done => {
+                // in a for-in loop the IterCheck sees this nil and ends the loop
+                self.stack.push(Value::Nil);
+                return Ok(());
+            }
+            GeneratorState::Running => {
+                return Err(self.runtime_error("Generator is already running."))
+            }
+            GeneratorState::Suspended => {}
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.runtime_error("Stack overflow."));
+        }
+
+        let slots = self.stack.len();
+        let (closure, ip, missing) = {
+            let mut paused = generator.borrow_mut();
+            paused.state = GeneratorState::Running;
+            self.stack.append(&mut paused.stack);
+            let depth = self.frames.len() + 1;
+            self.handlers
+                .extend(paused.handlers.drain(..).map(|(offset, catch_ip)| Handler {
+                    frame_depth: depth,
+                    stack_len: slots + offset,
+                    catch_ip,
+                }));
+            (
+                paused.closure.clone(),
+                paused.ip,
+                std::mem::take(&mut paused.missing),
+            )
+        };
+
+        self.push_frame(closure, slots, missing)?;
+        let frame = self.frame_mut();
+        frame.ip = ip;
+        frame.generator = Some((generator, for_in));
+        Ok(())
+    }
+
+    /// Pushes the module at path, running its top level first if it hasn't been imported yet
+    fn import(&mut self, path: &str) -> Result<(), InterpretError> {
+        // next to the importing file first, then the search paths
+        let importer = self.frame().closure.module.path.clone();
+        let directory = importer
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or_else(|| Path::new(""));
+        let mut candidates = vec![modules::normalize(&directory.join(path))];
+        candidates.extend(
+            self.search_paths
+                .iter()
+                .map(|search| modules::normalize(&search.join(path))),
+        );
+
+        for candidate in candidates {
+            if let Some(module) = self.modules.get(&candidate) {
+                self.stack.push(Value::Module(module.clone()));
+                return Ok(());
+            }
+
+            let cycle_start = self
+                .loading
+                .iter()
+                .position(|(module, _)| module.path.as_ref() == Some(&candidate));
+            if let Some(cycle_start) = cycle_start {
+                let mut cycle: Vec<String> = self.loading[cycle_start..]
+                    .iter()
+                    .map(|(module, _)| module.name.to_string())
+                    .collect();
+                cycle.push(candidate.display().to_string());
+                return Err(self.runtime_error(&format!("Import cycle: {}.", cycle.join(" -> "))));
+            }
+
+            let source = match self.module_loader.load(&candidate) {
+                Ok(source) => source,
+                Err(error) if error.kind() == ErrorKind::NotFound => continue,
+                Err(error) => {
+                    return Err(self
+                        .runtime_error(&format!("Could not read module '{}': {}.", path, error)))
+                }
+            };
+
+            let function = match compiler::compile(source) {
+                Ok(function) => function,
+                // the syntax errors were already reported
+                Err(_) => {
+                    return Err(self.runtime_error(&format!("Could not compile module '{}'.", path)))
+                }
+            };
+
+            let name = Rc::from(candidate.display().to_string());
+            let module = Rc::new(Module::new(name, Some(candidate)));
+            let closure = Rc::new(Closure::new(Rc::new(function), module.clone()));
+            self.stack.push(Value::Closure(closure.clone()));
+            self.call(closure, 0)?;
+            self.frame_mut().is_module = true;
+            self.loading.push((module, self.frames.len()));
+            return Ok(());
+        }
+
+        Err(self.runtime_error(&format!("Could not find module '{}'.", path)))
+    }
+
+    fn module_variable(&mut self, module: &Module, name: &str) -> Result<Value, InterpretError> {
+        let value = module.globals.borrow().get(name).cloned();
+        value.ok_or_else(|| {
+            self.runtime_error(&format!(
+                "Module '{}' has no variable '{}'.",
+                module.name, name
+            ))
+        })
+    }
+
+    fn invoke(&mut self, name: &Rc<str>, arg_count: usize) -> Result<(), InterpretError> {
+        let args_start = self.stack.len() - arg_count;
+        let instance = match self.peek(arg_count) {
+            Value::Instance(instance) => instance.clone(),
+            Value::Class(class) => {
+                let method = class.borrow().class_methods.get(name).cloned();
+                return match method {
+                    Some(method) => self.call(method, arg_count),
+                    None => Err(self.runtime_error(&format!("Undefined property '{}'.", name))),
+                };
+            }
+            Value::Enum(enum_) => {
+                let variant = match enum_.variant(name) {
+                    Some(variant) => variant,
+                    None => {
+                        return Err(self.runtime_error(&format!("Undefined property '{}'.", name)))
+                    }
+                };
+                let slot = self.stack.len() - arg_count - 1;
+                self.stack[slot] = variant.clone();
+                return self.call_value(variant, arg_count);
+            }
+            Value::Generator(generator) if &**name == "next" => {
+                let generator = generator.clone();
+                if arg_count != 0 {
+                    return Err(
+                        self.runtime_error(&format!("Expected 0 arguments but got {}.", arg_count))
+                    );
+                }
+                self.pop();
+                return self.resume(generator, false);
+            }
+            Value::Module(module) => {
+                let value = self.module_variable(&module.clone(), name)?;
+                let slot = self.stack.len() - arg_count - 1;
+                self.stack[slot] = value.clone();
+                return self.call_value(value, arg_count);
+            }
+            receiver => {
+                return match methods::call_method(receiver, name, &self.stack[args_start..]) {
+                    Some(Ok(value)) => {
+                        self.stack.truncate(args_start - 1);
+                        self.stack.push(value);
+                        Ok(())
+                    }
+                    Some(Err(message)) => Err(self.runtime_error(&message)),
+                    None => Err(self.runtime_error("Only instances have methods.")),
+                }
+            }
+        };
+
+        // a field holding a callable shadows a method of the same name
+        let field = instance.borrow().fields.get(name).cloned();
+        if let Some(value) = field {
+            let slot = self.stack.len() - arg_count - 1;
+            self.stack[slot] = value.clone();
+            return self.call_value(value, arg_count);
+        }
+
+        let class = instance.borrow().class.clone();
+        self.invoke_from_class(&class, name, arg_count)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: &Rc<RefCell<Class>>,
+        name: &Rc<str>,
+        arg_count: usize,
+    ) -> Result<(), InterpretError> {
+        let method = class.borrow().methods.get(name).cloned();
+        match method {
+            Some(method) => self.call(method, arg_count),
+            None if class.borrow().getters.contains_key(name) => {
+                Err(self.runtime_error(&format!("Getter '{}' is not called with arguments.", name)))
+            }
+            None => Err(self.runtime_error(&format!("Undefined property '{}'.", name))),
+        }
+    }
+
+    /// Replaces the instance on top of the stack with its method bound to it, or with what its
+    /// getter returns
+    fn bind_method(
+        &mut self,
+        class: &Rc<RefCell<Class>>,
+        name: &Rc<str>,
+    ) -> Result<(), InterpretError> {
+        let method = class.borrow().methods.get(name).cloned();
+        if let Some(method) = method {
+            self.bind(method);
+            return Ok(());
+        }
+
+        let getter = class.borrow().getters.get(name).cloned();
+        match getter {
+            // the instance on the stack becomes the getter's this
+            Some(getter) => self.call(getter, 0),
+            None => Err(self.runtime_error(&format!("Undefined property '{}'.", name))),
+        }
+    }
+
+    fn bind(&mut self, method: Rc<Closure>) {
+        let receiver = self.pop();
+        self.stack.push(Value::BoundMethod(Rc::new(BoundMethod {
+            receiver,
+            method,
+        })));
+    }
+
+    /// Returns the upvalue for a stack slot, reusing an open one so sibling closures share it
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let position = self
+            .open_upvalues
+            .iter()
+            .position(|upvalue| match &*upvalue.borrow() {
+                Upvalue::Open(index) => *index >= slot,
+                Upvalue::Closed(_) => false,
+            });
+
+        if let Some(position) = position {
+            let existing = &self.open_upvalues[position];
+            if matches!(&*existing.borrow(), Upvalue::Open(index) if *index == slot) {
+                return existing.clone();
+            }
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        let position = position.unwrap_or(self.open_upvalues.len());
+        self.open_upvalues.insert(position, upvalue.clone());
+        upvalue
+    }
+
+    /// Moves every open upvalue at or above last_slot off the stack
+    fn close_upvalues(&mut self, last_slot: usize) {
+        while let Some(upvalue) = self.open_upvalues.last() {
+            let slot = match &*upvalue.borrow() {
+                Upvalue::Open(index) if *index >= last_slot => *index,
+                _ => break,
+            };
+
+            let upvalue = self.open_upvalues.pop().unwrap();
+            *upvalue.borrow_mut() = Upvalue::Closed(self.stack[slot].clone());
+        }
+    }
+
+    fn get_index(&mut self, container: &Value, index: &Value) -> Result<Value, InterpretError> {
+        let value = match container {
+            Value::List(list) => {
+                let list = list.borrow();
+                list_index(list.len(), index).map(|index| list[index].clone())
+            }
+            Value::Map(map) => match map.borrow().get(index) {
+                Ok(Some(value)) => Ok(value.clone()),
+                Ok(None) => Err(format!("Undefined key {}.", index.repr())),
+                Err(message) => Err(message),
+            },
+            Value::LoxString(string) => string_index(string, index),
+            _ => Err(String::from("Only lists, maps and strings can be indexed.")),
+        };
+        value.map_err(|message| self.runtime_error(&message))
+    }
+
+    fn set_index(
+        &mut self,
+        container: &Value,
+        index: Value,
+        value: Value,
+    ) -> Result<(), InterpretError> {
+        let result = match container {
+            Value::List(list) => {
+                let mut list = list.borrow_mut();
+                list_index(list.len(), &index).map(|index| list[index] = value)
+            }
+            Value::Map(map) => map.borrow_mut().insert(index, value),
+            Value::LoxString(_) => Err(String::from("Strings can't be changed by index.")),
+            _ => Err(String::from("Only lists, maps and strings can be indexed.")),
+        };
+        result.map_err(|message| self.runtime_error(&message))
+    }
+
+    /// The special method name on the class of the operand arg_count down the stack, when it is
+    /// an instance whose class defines one
+    fn special_method(&self, name: &str, arg_count: usize) -> Option<Rc<Closure>> {
+        match self.peek(arg_count) {
+            Value::Instance(instance) => {
+                instance.borrow().class.borrow().methods.get(name).cloned()
+            }
+            _ => None,
+        }
+    }
+
+    /// Calls the special method if there is one, false when the built-in operator should run
+    fn overload(&mut self, name: &str, arg_count: usize) -> Result<bool, InterpretError> {
+        match self.special_method(name, arg_count) {
+            Some(method) => self.call(method, arg_count).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Tries the left operand's method, then the right operand's reflected one with the operands
+    /// swapped, so a < b also works when only b defines __gt__
+    fn overload_binary(
+        &mut self,
+        name: &str,
+        reflected: Option<&str>,
+    ) -> Result<bool, InterpretError> {
+        if self.overload(name, 1)? {
+            return Ok(true);
+        }
+        let reflected = match reflected {
+            Some(reflected) => reflected,
+            None => return Ok(false),
+        };
+
+        let len = self.stack.len();
+        self.stack.swap(len - 1, len - 2);
+        if self.overload(reflected, 1)? {
+            return Ok(true);
+        }
+        self.stack.swap(len - 1, len - 2);
+        Ok(false)
+    }
+
+    fn binary_number_op(&mut self, op: fn(f64, f64) -> Value) -> Result<(), InterpretError> {
+        let (a, b) = match (self.peek(1), self.peek(0)) {
+            (Value::Number(a), Value::Number(b)) => (*a, *b),
+            _ => return Err(self.runtime_error("Operands must be numbers.")),
+        };
+
+        self.pop();
+        self.pop();
+        self.stack.push(op(a, b));
+        Ok(())
+    }
+
+    fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        self.insert_native(name, Some(arity), function);
+    }
+
+    /// Defines a native that checks its own argument count
+    fn define_variadic_native(&mut self, name: &str, function: NativeFn) {
+        self.insert_native(name, None, function);
+    }
+
+    fn insert_native(&mut self, name: &str, arity: Option<usize>, function: NativeFn) {
+        let name: Rc<str> = Rc::from(name);
+        let native = Native {
+            name: name.clone(),
+            arity,
+            function,
+        };
+        self.builtins.insert(name, Value::Native(Rc::new(native)));
+    }
+
+    /// Throws an Error instance carrying message
+    fn runtime_error(&mut self, message: &str) -> InterpretError {
+        let mut error = Instance::new(self.error_class.clone());
+        error
+            .fields
+            .insert(Rc::from("message"), Value::LoxString(Rc::from(message)));
+        self.throw(Value::Instance(Rc::new(RefCell::new(error))))
+    }
+
+    /// Records value as thrown, the error is what gets reported if nothing catches it
+    fn throw(&mut self, value: Value) -> InterpretError {
+        self.trace = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| {
+                let function = &frame.closure.function;
+                let line = function.chunk.line(frame.ip - 1);
+                match &function.name {
+                    Some(name) => format!("[line {}] in {}()", line, name),
+                    None => format!("[line {}] in script", line),
+                }
+            })
+            .collect();
+
+        let mut message = None;
+        if let Value::Instance(instance) = &value {
+            let mut instance = instance.borrow_mut();
+            // a rethrown error keeps the line and stack it was first thrown from
+            if !instance.fields.contains_key("stack") {
+                let frame = self.frame();
+                let line = frame.closure.function.chunk.line(frame.ip - 1);
+                let stack = self
+                    .trace
+                    .iter()
+                    .map(|line| Value::LoxString(Rc::from(line.as_str())))
+                    .collect();
+                instance
+                    .fields
+                    .insert(Rc::from("line"), Value::Number(line as f64));
+                instance
+                    .fields
+                    .insert(Rc::from("stack"), Value::List(Rc::new(RefCell::new(stack))));
+            }
+            message = instance.fields.get("message").map(Value::to_string);
+        }
+
+        let message = message.unwrap_or_else(|| format!("Uncaught exception {}.", value.repr()));
+        self.thrown = Some(value);
+        InterpretError::RuntimeError(message)
+    }
+
+    /// Unwinds to the innermost handler and pushes the thrown value for it, false when there is none
+    fn catch(&mut self) -> bool {
+        let handler = match self.handlers.pop() {
+            Some(handler) => handler,
+            None => return false,
+        };
+
+        let thrown = self.thrown.take().unwrap_or(Value::Nil);
+        self.discard_frames(handler.frame_depth);
+        // modules that failed part way through stay unloaded, a later import runs them again
+        self.loading
+            .retain(|(_, depth)| *depth <= handler.frame_depth);
+        self.close_upvalues(handler.stack_len);
+        self.stack.truncate(handler.stack_len);
+        self.stack.push(thrown);
+        self.frame_mut().ip = handler.catch_ip;
+        true
+    }
+
+    /// Reports an uncaught error with its stack trace and resets the vm
+    fn abort(&mut self, error: InterpretError) -> InterpretError {
+        error!("{}", error);
+        for line in self.trace.drain(..) {
+            error!("{}", line);
+        }
+
+        self.reset();
+        error
+    }
+
+    /// Clears everything the interrupted script left behind, globals are kept
+    fn reset(&mut self) {
+        // closures that outlive the error must not keep pointing into the cleared stack
+        self.close_upvalues(0);
+        self.stack.clear();
+        self.discard_frames(0);
+        self.handlers.clear();
+        self.loading.clear();
+        self.thrown = None;
+    }
+
+    /// Pops the frames above depth, a generator they were running can't be resumed
+    fn discard_frames(&mut self, depth: usize) {
+        for frame in self.frames.drain(depth..) {
+            if let Some((generator, _)) = frame.generator {
+                generator.borrow_mut().state = GeneratorState::Done;
+            }
+        }
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_short(&mut self) -> u16 {
+        u16::from_be_bytes([self.read_byte(), self.read_byte()])
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_byte() as usize;
+        self.frame().closure.function.chunk.constants[index].clone()
+    }
+
+    fn read_string(&mut self) -> Rc<str> {
+        match self.read_constant() {
+            Value::LoxString(name) => name,
+            constant => panic!("Expected string constant, found {}", constant),
+        }
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Value stack underflow")
+    }
+}
+
+fn arity_error(function: &Function, arg_count: usize) -> String {
+    let min_arity = function.arity - function.optional;
+    if function.has_rest {
+        format!(
+            "Expected at least {} arguments but got {}.",
+            min_arity, arg_count
+        )
+    } else if function.optional > 0 {
+        format!(
+            "Expected {} to {} arguments but got {}.",
+            min_arity, function.arity, arg_count
+        )
+    } else {
+        format!(
+            "Expected {} arguments but got {}.",
+            function.arity, arg_count
+        )
+    }
+}
+
+/// Whether class is ancestor or one of its subclasses
+fn is_subclass(class: &Rc<RefCell<Class>>, ancestor: &Rc<RefCell<Class>>) -> bool {
+    let mut class = Some(class.clone());
+    while let Some(current) = class {
+        if Rc::ptr_eq(&current, ancestor) {
+            return true;
+        }
+        class = current.borrow().superclass.clone();
+    }
+    false
+}
+
+/// Checks a list index, lists are indexed from zero
+/// The character at a char (not byte) index, as a one character string
+fn string_index(string: &str, index: &Value) -> Result<Value, String> {
+    match index {
+        Value::Number(index) if index.fract() == 0.0 && *index >= 0.0 => string
+            .chars()
+            .nth(*index as usize)
+            .map(|c| Value::LoxString(c.to_string().into()))
+            .ok_or_else(|| String::from("String index out of range.")),
+        _ => Err(String::from("String index must be a non-negative integer.")),
+    }
+}
+
+fn list_index(len: usize, index: &Value) -> Result<usize, String> {
+    match index {
+        Value::Number(index) if index.fract() == 0.0 && *index >= 0.0 => {
+            if *index < len as f64 {
+                Ok(*index as usize)
+            } else {
+                Err(String::from("List index out of range."))
+            }
+        }
+        _ => Err(String::from("List index must be a non-negative integer.")),
+    }
+}
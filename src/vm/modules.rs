@@ -0,0 +1,39 @@
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Reads the source of imported modules, tests swap in one backed by memory
+pub trait ModuleLoader {
+    /// Source of the file at path, an error of kind NotFound moves on to the next search path
+    fn load(&self, path: &Path) -> io::Result<String>;
+}
+
+/// Loads modules from the file system
+#[derive(Debug, Default)]
+pub struct FileLoader;
+
+impl ModuleLoader for FileLoader {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+}
+
+/// Drops `.` and folds `dir/..` so one file gets one cache entry however it is spelled
+pub(super) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
@@ -0,0 +1,884 @@
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::env;
+use std::f64::consts;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::iter;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::{json, Vm};
+use crate::value::Value;
+
+/// State natives share with the vm that runs them, every vm has its own
+#[derive(Debug)]
+pub struct NativeContext {
+    random_state: u64,
+    // off for sandboxed runs, the file, stdin, environment and exit natives fail instead
+    pub(super) io_enabled: bool,
+    // what args() returns, the command line arguments after the script path
+    pub(super) args: Vec<Rc<str>>,
+    // set by exit(), the vm stops once the native returns
+    pub(super) exit_code: Option<i32>,
+}
+
+impl NativeContext {
+    pub(super) fn new() -> Self {
+        // every vm starts from the same seed so scripts print the same numbers on every run
+        Self {
+            random_state: DEFAULT_SEED,
+            io_enabled: true,
+            args: Vec::new(),
+            exit_code: None,
+        }
+    }
+
+    fn check_io(&self, name: &str) -> Result<(), String> {
+        if self.io_enabled {
+            Ok(())
+        } else {
+            Err(format!("{}() is not available in a sandbox.", name))
+        }
+    }
+}
+
+/// Registers the standard library globals on a new vm
+pub(super) fn define_natives<W: Write>(vm: &mut Vm<W>) {
+    vm.define_native("clock", 0, clock);
+
+    vm.define_native("len", 1, len);
+    vm.define_native("upper", 1, upper);
+    vm.define_native("lower", 1, lower);
+    vm.define_native("trim", 1, trim);
+    vm.define_native("replace", 3, replace);
+    vm.define_native("find", 2, find);
+    vm.define_native("starts_with", 2, starts_with);
+    vm.define_native("ends_with", 2, ends_with);
+    vm.define_native("substr", 3, substr);
+    vm.define_native("char_at", 2, char_at);
+    vm.define_native("repeat", 2, repeat);
+    vm.define_native("split", 2, split);
+    vm.define_native("join", 2, join);
+    vm.define_native("parse_number", 1, parse_number);
+    vm.define_native("to_string", 1, to_string);
+
+    vm.define_native("sqrt", 1, sqrt);
+    vm.define_native("pow", 2, pow);
+    vm.define_native("floor", 1, floor);
+    vm.define_native("ceil", 1, ceil);
+    vm.define_native("round", 1, round);
+    vm.define_native("abs", 1, abs);
+    vm.define_native("min", 2, min);
+    vm.define_native("max", 2, max);
+    vm.define_native("sin", 1, sin);
+    vm.define_native("cos", 1, cos);
+    vm.define_native("tan", 1, tan);
+    vm.define_native("asin", 1, asin);
+    vm.define_native("acos", 1, acos);
+    vm.define_native("atan", 1, atan);
+    vm.define_native("atan2", 2, atan2);
+    vm.builtins
+        .insert(Rc::from("pi"), Value::Number(consts::PI));
+    vm.builtins.insert(Rc::from("e"), Value::Number(consts::E));
+
+    vm.define_native("random", 0, random);
+    vm.define_native("random_int", 2, random_int);
+    vm.define_native("seed", 1, seed);
+
+    vm.define_native("now", 0, now);
+    vm.define_native("monotonic", 0, monotonic);
+    vm.define_native("sleep", 1, sleep);
+    vm.define_native("utc_year", 1, utc_year);
+    vm.define_native("utc_month", 1, utc_month);
+    vm.define_native("utc_day", 1, utc_day);
+    vm.define_native("utc_hour", 1, utc_hour);
+    vm.define_native("utc_minute", 1, utc_minute);
+    vm.define_native("utc_second", 1, utc_second);
+    vm.define_variadic_native("format", format);
+
+    vm.define_native("type", 1, type_of);
+    vm.define_native("has_field", 2, has_field);
+    vm.define_native("get_field", 2, get_field);
+    vm.define_native("set_field", 3, set_field);
+    vm.define_native("fields", 1, fields);
+    vm.define_native("methods", 1, methods);
+    vm.define_native("class_of", 1, class_of);
+    vm.define_native("superclass", 1, superclass);
+    vm.define_native("arity", 1, arity);
+
+    vm.define_native("read_file", 1, read_file);
+    vm.define_native("write_file", 2, write_file);
+    vm.define_native("append_file", 2, append_file);
+    vm.define_native("read_line", 0, read_line);
+    vm.define_native("file_exists", 1, file_exists);
+    vm.define_native("list_dir", 1, list_dir);
+    vm.define_native("args", 0, args);
+    vm.define_native("env", 1, env);
+    vm.define_native("exit", 1, exit);
+
+    vm.builtins
+        .insert(Rc::from("json"), Value::Module(Rc::new(json::module())));
+}
+
+fn clock(_context: &mut NativeContext, _args: &[Value]) -> Result<Value, String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| err.to_string())?;
+    Ok(Value::Number(now.as_secs_f64()))
+}
+
+// Strings are indexed and measured in chars, not bytes
+
+fn len(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let len = match &args[0] {
+        Value::LoxString(string) => string.chars().count(),
+        Value::List(list) => list.borrow().len(),
+        Value::Map(map) => map.borrow().len(),
+        _ => {
+            return Err(String::from(
+                "Argument 1 to len() must be a string, list or map.",
+            ))
+        }
+    };
+    Ok(Value::Number(len as f64))
+}
+
+fn upper(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "upper")?;
+    Ok(string_value(string.to_uppercase()))
+}
+
+fn lower(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "lower")?;
+    Ok(string_value(string.to_lowercase()))
+}
+
+fn trim(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "trim")?;
+    Ok(string_value(string.trim()))
+}
+
+fn replace(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "replace")?;
+    let from = string_arg(args, 1, "replace")?;
+    let to = string_arg(args, 2, "replace")?;
+    Ok(string_value(string.replace(from, to)))
+}
+
+/// Char index of the first match, or -1
+fn find(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "find")?;
+    let pattern = string_arg(args, 1, "find")?;
+    let index = match string.find(pattern) {
+        Some(byte_index) => string[..byte_index].chars().count() as f64,
+        None => -1.0,
+    };
+    Ok(Value::Number(index))
+}
+
+fn starts_with(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "starts_with")?;
+    let prefix = string_arg(args, 1, "starts_with")?;
+    Ok(Value::Bool(string.starts_with(prefix)))
+}
+
+fn ends_with(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "ends_with")?;
+    let suffix = string_arg(args, 1, "ends_with")?;
+    Ok(Value::Bool(string.ends_with(suffix)))
+}
+
+/// substr(string, start, length)
+fn substr(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "substr")?;
+    let start = index_arg(args, 1, "substr")?;
+    let length = index_arg(args, 2, "substr")?;
+    let rest = string.chars().count().checked_sub(start);
+    if rest.is_none_or(|rest| length > rest) {
+        return Err(String::from("substr() range is out of bounds."));
+    }
+
+    let substring: String = string.chars().skip(start).take(length).collect();
+    Ok(string_value(substring))
+}
+
+fn char_at(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "char_at")?;
+    let index = index_arg(args, 1, "char_at")?;
+    match string.chars().nth(index) {
+        Some(c) => Ok(string_value(c.to_string())),
+        None => Err(String::from("char_at() index is out of bounds.")),
+    }
+}
+
+// longest string repeat() will build, far more than a script needs but short of exhausting memory
+const REPEAT_MAX_BYTES: usize = 1 << 28;
+
+fn repeat(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "repeat")?;
+    let count = index_arg(args, 1, "repeat")?;
+    match string.len().checked_mul(count) {
+        Some(bytes) if bytes <= REPEAT_MAX_BYTES => Ok(string_value(string.repeat(count))),
+        _ => Err(String::from("repeat() result is too long.")),
+    }
+}
+
+/// split(string, separator), an empty separator splits into characters
+fn split(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "split")?;
+    let separator = string_arg(args, 1, "split")?;
+    let parts: Vec<Value> = if separator.is_empty() {
+        string
+            .chars()
+            .map(|c| string_value(c.to_string()))
+            .collect()
+    } else {
+        string.split(separator).map(string_value).collect()
+    };
+    Ok(Value::List(Rc::new(RefCell::new(parts))))
+}
+
+/// join(list, separator), the list must hold only strings
+fn join(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let error = || String::from("Argument 1 to join() must be a list of strings.");
+    let list = match &args[0] {
+        Value::List(list) => list.borrow(),
+        _ => return Err(error()),
+    };
+    let separator = string_arg(args, 1, "join")?;
+    let parts = list
+        .iter()
+        .map(|value| match value {
+            Value::LoxString(string) => Ok(&**string),
+            _ => Err(error()),
+        })
+        .collect::<Result<Vec<&str>, String>>()?;
+    Ok(string_value(parts.join(separator)))
+}
+
+/// The number the string holds, or nil when it isn't one
+fn parse_number(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let string = string_arg(args, 0, "parse_number")?;
+    match string.trim().parse::<f64>() {
+        Ok(number) => Ok(Value::Number(number)),
+        Err(_) => Ok(Value::Nil),
+    }
+}
+
+fn to_string(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(string_value(args[0].to_string()))
+}
+
+fn sqrt(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(number_arg(args, 0, "sqrt")?.sqrt()))
+}
+
+fn pow(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let base = number_arg(args, 0, "pow")?;
+    let exponent = number_arg(args, 1, "pow")?;
+    Ok(Value::Number(base.powf(exponent)))
+}
+
+fn floor(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(number_arg(args, 0, "floor")?.floor()))
+}
+
+fn ceil(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(number_arg(args, 0, "ceil")?.ceil()))
+}
+
+/// Rounds half way cases away from zero
+fn round(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(number_arg(args, 0, "round")?.round()))
+}
+
+fn abs(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(number_arg(args, 0, "abs")?.abs()))
+}
+
+fn min(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let a = number_arg(args, 0, "min")?;
+    let b = number_arg(args, 1, "min")?;
+    Ok(Value::Number(a.min(b)))
+}
+
+fn max(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let a = number_arg(args, 0, "max")?;
+    let b = number_arg(args, 1, "max")?;
+    Ok(Value::Number(a.max(b)))
+}
+
+fn sin(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(number_arg(args, 0, "sin")?.sin()))
+}
+
+fn cos(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(number_arg(args, 0, "cos")?.cos()))
+}
+
+fn tan(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(number_arg(args, 0, "tan")?.tan()))
+}
+
+fn asin(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(number_arg(args, 0, "asin")?.asin()))
+}
+
+fn acos(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(number_arg(args, 0, "acos")?.acos()))
+}
+
+fn atan(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    Ok(Value::Number(number_arg(args, 0, "atan")?.atan()))
+}
+
+/// atan2(y, x)
+fn atan2(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let y = number_arg(args, 0, "atan2")?;
+    let x = number_arg(args, 1, "atan2")?;
+    Ok(Value::Number(y.atan2(x)))
+}
+
+const DEFAULT_SEED: u64 = 0;
+// f64 holds every integer up to 2^53, random_int() can't return anything past that exactly
+const RANDOM_INT_MAX: i64 = 1 << 53;
+
+/// splitmix64, small and good enough for scripts, and the same on every platform
+fn next_random(context: &mut NativeContext) -> u64 {
+    context.random_state = context.random_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+
+    let mut z = context.random_state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A number in [0, 1)
+fn random(context: &mut NativeContext, _args: &[Value]) -> Result<Value, String> {
+    // the top 53 bits fill an f64 mantissa exactly
+    let fraction = (next_random(context) >> 11) as f64 / (1u64 << 53) as f64;
+    Ok(Value::Number(fraction))
+}
+
+/// An integer in [low, high], both ends included
+fn random_int(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let low = integer_arg(args, 0, "random_int")?;
+    let high = integer_arg(args, 1, "random_int")?;
+    if low < -RANDOM_INT_MAX || high > RANDOM_INT_MAX {
+        return Err(String::from(
+            "random_int() bounds must be between -2^53 and 2^53.",
+        ));
+    }
+    if low > high {
+        return Err(String::from("random_int() range is empty."));
+    }
+
+    // the bounds check above keeps high - low well inside i64
+    let range = (high - low) as u64 + 1;
+    let offset = (next_random(context) % range) as i64;
+    Ok(Value::Number((low + offset) as f64))
+}
+
+fn seed(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let seed = integer_arg(args, 0, "seed")?;
+    context.random_state = seed as u64;
+    Ok(Value::Nil)
+}
+
+/// Milliseconds since the unix epoch
+fn now(_context: &mut NativeContext, _args: &[Value]) -> Result<Value, String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|err| err.to_string())?;
+    Ok(Value::Number(now.as_millis() as f64))
+}
+
+/// Milliseconds from a fixed point, never goes backwards unlike now()
+fn monotonic(_context: &mut NativeContext, _args: &[Value]) -> Result<Value, String> {
+    static START: OnceLock<Instant> = OnceLock::new();
+    let elapsed = START.get_or_init(Instant::now).elapsed();
+    Ok(Value::Number(elapsed.as_secs_f64() * 1000.0))
+}
+
+fn sleep(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let millis = number_arg(args, 0, "sleep")?;
+    // also rules out NaN, which compares false against everything
+    if !(millis.is_finite() && millis >= 0.0) {
+        return Err(String::from(
+            "sleep() duration must be a finite, non-negative number.",
+        ));
+    }
+
+    let duration = Duration::try_from_secs_f64(millis / 1000.0)
+        .map_err(|_| String::from("sleep() duration is too long."))?;
+    thread::sleep(duration);
+    Ok(Value::Nil)
+}
+
+fn utc_year(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    utc_field(args, "utc_year", 0)
+}
+
+fn utc_month(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    utc_field(args, "utc_month", 1)
+}
+
+fn utc_day(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    utc_field(args, "utc_day", 2)
+}
+
+fn utc_hour(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    utc_field(args, "utc_hour", 3)
+}
+
+fn utc_minute(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    utc_field(args, "utc_minute", 4)
+}
+
+fn utc_second(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    utc_field(args, "utc_second", 5)
+}
+
+fn utc_field(args: &[Value], name: &str, field: usize) -> Result<Value, String> {
+    let millis = number_arg(args, 0, name)?;
+    Ok(Value::Number(utc_fields(millis)[field] as f64))
+}
+
+/// Splits epoch millis into year, month, day, hour, minute and second in UTC
+fn utc_fields(millis: f64) -> [i64; 6] {
+    let seconds = (millis / 1000.0).floor() as i64;
+    let days = seconds.div_euclid(86_400);
+    let second_of_day = seconds.rem_euclid(86_400);
+
+    // days to a civil date, from Howard Hinnant's chrono-compatible algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // months counted from march, so the leap day comes last
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    [
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day % 3600 / 60,
+        second_of_day % 60,
+    ]
+}
+
+/// format(template, ...args) fills each {} or {:spec} in template with the next argument.
+/// A spec is [[fill]align][width][.precision] with align one of < > ^, as in Rust.
+fn format(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    if args.is_empty() {
+        return Err(String::from("Expected at least 1 argument but got 0."));
+    }
+    let template = string_arg(args, 0, "format")?;
+    let mut values = args[1..].iter();
+
+    let mut output = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let mut placeholder = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => placeholder.push(c),
+                        None => return Err(String::from("format() has an unclosed '{'.")),
+                    }
+                }
+
+                let spec = match placeholder.strip_prefix(':') {
+                    Some(spec) => FormatSpec::parse(spec),
+                    None if placeholder.is_empty() => Some(FormatSpec::default()),
+                    None => None,
+                };
+                let spec = spec.ok_or_else(|| {
+                    format!("format() has an invalid placeholder '{{{}}}'.", placeholder)
+                })?;
+                let value = values.next().ok_or_else(|| {
+                    String::from("format() has more placeholders than arguments.")
+                })?;
+                spec.write(value, &mut output);
+            }
+            '}' => return Err(String::from("format() has an unmatched '}'.")),
+            c => output.push(c),
+        }
+    }
+
+    if values.next().is_some() {
+        return Err(String::from(
+            "format() has more arguments than placeholders.",
+        ));
+    }
+    Ok(string_value(output))
+}
+
+#[derive(Default)]
+struct FormatSpec {
+    fill: Option<char>,
+    align: Option<char>,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl FormatSpec {
+    fn parse(spec: &str) -> Option<Self> {
+        let mut result = FormatSpec::default();
+        let chars: Vec<char> = spec.chars().collect();
+        let is_align = |c: Option<&char>| matches!(c, Some('<') | Some('>') | Some('^'));
+
+        let mut rest = &chars[..];
+        if is_align(chars.get(1)) {
+            result.fill = Some(chars[0]);
+            result.align = Some(chars[1]);
+            rest = &chars[2..];
+        } else if is_align(chars.first()) {
+            result.align = Some(chars[0]);
+            rest = &chars[1..];
+        }
+
+        let rest: String = rest.iter().collect();
+        let (width, precision) = match rest.split_once('.') {
+            Some((width, precision)) => (width, Some(precision)),
+            None => (rest.as_str(), None),
+        };
+        if !width.is_empty() {
+            result.width = width.parse().ok()?;
+        }
+        if let Some(precision) = precision {
+            result.precision = Some(precision.parse().ok()?);
+        }
+
+        Some(result)
+    }
+
+    fn write(&self, value: &Value, output: &mut String) {
+        let text = match (value, self.precision) {
+            (Value::Number(number), Some(precision)) => format!("{:.*}", precision, number),
+            (value, Some(precision)) => value.to_string().chars().take(precision).collect(),
+            (value, None) => value.to_string(),
+        };
+
+        // numbers line up on the right by default, everything else on the left
+        let default_align = if let Value::Number(_) = value {
+            '>'
+        } else {
+            '<'
+        };
+        let padding = self.width.saturating_sub(text.chars().count());
+        let (before, after) = match self.align.unwrap_or(default_align) {
+            '>' => (padding, 0),
+            '^' => (padding / 2, padding - padding / 2),
+            _ => (0, padding),
+        };
+
+        let fill = self.fill.unwrap_or(' ');
+        output.extend(iter::repeat_n(fill, before));
+        output.push_str(&text);
+        output.extend(iter::repeat_n(fill, after));
+    }
+}
+
+fn type_of(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let name = match &args[0] {
+        Value::Nil => "nil",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::LoxString(_) => "string",
+        Value::Function(_) | Value::Closure(_) | Value::Native(_) | Value::BoundMethod(_) => {
+            "function"
+        }
+        Value::Class(_) => "class",
+        Value::Instance(_) => "instance",
+        Value::List(_) => "list",
+        Value::Map(_) => "map",
+        Value::Module(_) => "module",
+        Value::Trait(_) => "trait",
+        Value::Range(_) => "range",
+        Value::Iterator(_) => "iterator",
+        Value::Generator(_) => "generator",
+        Value::Enum(_) => "enum",
+        Value::EnumVariant(_) => "function",
+        Value::EnumValue(_) => "variant",
+    };
+    Ok(string_value(name))
+}
+
+/// False for anything that isn't an instance, so it is safe to probe any value
+fn has_field(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let name = string_arg(args, 1, "has_field")?;
+    let has_field = match &args[0] {
+        Value::Instance(instance) => instance.borrow().fields.contains_key(name),
+        _ => false,
+    };
+    Ok(Value::Bool(has_field))
+}
+
+fn get_field(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let name = string_arg(args, 1, "get_field")?;
+    let instance = match &args[0] {
+        Value::Instance(instance) => instance,
+        _ => return Err(String::from("Only instances have fields.")),
+    };
+
+    let field = instance.borrow().fields.get(name).cloned();
+    field.ok_or_else(|| format!("Undefined property '{}'.", name))
+}
+
+fn set_field(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let name = string_arg(args, 1, "set_field")?;
+    let instance = match &args[0] {
+        Value::Instance(instance) => instance,
+        _ => return Err(String::from("Only instances have fields.")),
+    };
+
+    let value = args[2].clone();
+    instance
+        .borrow_mut()
+        .fields
+        .insert(Rc::from(name), value.clone());
+    Ok(value)
+}
+
+/// Names of the instance's fields, sorted since they are kept in a hash map
+fn fields(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Instance(instance) => Ok(sorted_names(instance.borrow().fields.keys())),
+        _ => Err(String::from("Argument 1 to fields() must be an instance.")),
+    }
+}
+
+/// Names of the methods instances of the class have, inherited ones included
+fn methods(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Class(class) => Ok(sorted_names(class.borrow().methods.keys())),
+        _ => Err(String::from("Argument 1 to methods() must be a class.")),
+    }
+}
+
+fn sorted_names<'a>(names: impl Iterator<Item = &'a Rc<str>>) -> Value {
+    let mut names: Vec<Rc<str>> = names.cloned().collect();
+    names.sort();
+    let names = names.into_iter().map(Value::LoxString).collect();
+    Value::List(Rc::new(RefCell::new(names)))
+}
+
+fn class_of(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Instance(instance) => Ok(Value::Class(instance.borrow().class.clone())),
+        _ => Err(String::from(
+            "Argument 1 to class_of() must be an instance.",
+        )),
+    }
+}
+
+/// The class it inherits from, or nil
+fn superclass(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    match &args[0] {
+        Value::Class(class) => Ok(match &class.borrow().superclass {
+            Some(superclass) => Value::Class(superclass.clone()),
+            None => Value::Nil,
+        }),
+        _ => Err(String::from("Argument 1 to superclass() must be a class.")),
+    }
+}
+
+/// Number of arguments a call takes, nil for natives that take any number
+fn arity(_context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    let arity = match &args[0] {
+        Value::Function(function) => Some(function.arity),
+        Value::Closure(closure) => Some(closure.function.arity),
+        Value::BoundMethod(bound) => Some(bound.method.function.arity),
+        Value::Native(native) => native.arity,
+        Value::Class(class) => {
+            let init = class.borrow().methods.get("init").cloned();
+            Some(init.map_or(0, |init| init.function.arity))
+        }
+        _ => {
+            return Err(String::from(
+                "Argument 1 to arity() must be a function or class.",
+            ))
+        }
+    };
+    Ok(arity.map_or(Value::Nil, |arity| Value::Number(arity as f64)))
+}
+
+// File and process natives, all but args() fail when io is disabled
+
+fn read_file(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    context.check_io("read_file")?;
+    let path = string_arg(args, 0, "read_file")?;
+    fs::read_to_string(path)
+        .map(string_value)
+        .map_err(|err| io_error("read", path, err))
+}
+
+fn write_file(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    context.check_io("write_file")?;
+    let path = string_arg(args, 0, "write_file")?;
+    let contents = string_arg(args, 1, "write_file")?;
+    fs::write(path, contents).map_err(|err| io_error("write", path, err))?;
+    Ok(Value::Nil)
+}
+
+/// Adds to the end of the file, creating it when it doesn't exist
+fn append_file(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    context.check_io("append_file")?;
+    let path = string_arg(args, 0, "append_file")?;
+    let contents = string_arg(args, 1, "append_file")?;
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|err| io_error("write", path, err))?;
+    Ok(Value::Nil)
+}
+
+/// The next line from stdin without its line ending, nil at the end of input
+fn read_line(context: &mut NativeContext, _args: &[Value]) -> Result<Value, String> {
+    context.check_io("read_line")?;
+    let mut line = String::new();
+    let read = io::stdin()
+        .read_line(&mut line)
+        .map_err(|err| format!("Could not read from stdin: {}.", err))?;
+    if read == 0 {
+        return Ok(Value::Nil);
+    }
+
+    let end = line.trim_end_matches(&['\n', '\r'][..]).len();
+    line.truncate(end);
+    Ok(string_value(line))
+}
+
+fn file_exists(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    context.check_io("file_exists")?;
+    let path = string_arg(args, 0, "file_exists")?;
+    Ok(Value::Bool(Path::new(path).exists()))
+}
+
+/// Names of the entries in a directory, sorted so the order is the same on every platform
+fn list_dir(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    context.check_io("list_dir")?;
+    let path = string_arg(args, 0, "list_dir")?;
+    let mut names = fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().into_owned()))
+                .collect::<io::Result<Vec<String>>>()
+        })
+        .map_err(|err| io_error("list", path, err))?;
+    names.sort();
+    let names = names.into_iter().map(string_value).collect();
+    Ok(Value::List(Rc::new(RefCell::new(names))))
+}
+
+fn args(context: &mut NativeContext, _args: &[Value]) -> Result<Value, String> {
+    let args = context.args.iter().cloned().map(Value::LoxString).collect();
+    Ok(Value::List(Rc::new(RefCell::new(args))))
+}
+
+/// The environment variable's value, or nil when it isn't set
+fn env(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    context.check_io("env")?;
+    let name = string_arg(args, 0, "env")?;
+    Ok(env::var(name).map_or(Value::Nil, string_value))
+}
+
+fn exit(context: &mut NativeContext, args: &[Value]) -> Result<Value, String> {
+    context.check_io("exit")?;
+    let code = integer_arg(args, 0, "exit")?;
+    let code = i32::try_from(code).map_err(|_| String::from("exit() code is out of range."))?;
+    context.exit_code = Some(code);
+    Ok(Value::Nil)
+}
+
+fn io_error(action: &str, path: &str, err: io::Error) -> String {
+    format!("Could not {} '{}': {}.", action, path, err)
+}
+
+fn string_value(string: impl Into<Rc<str>>) -> Value {
+    Value::LoxString(string.into())
+}
+
+fn string_arg<'a>(args: &'a [Value], index: usize, name: &str) -> Result<&'a str, String> {
+    match &args[index] {
+        Value::LoxString(string) => Ok(string),
+        _ => Err(format!(
+            "Argument {} to {}() must be a string.",
+            index + 1,
+            name
+        )),
+    }
+}
+
+fn number_arg(args: &[Value], index: usize, name: &str) -> Result<f64, String> {
+    match args[index] {
+        Value::Number(number) => Ok(number),
+        _ => Err(format!(
+            "Argument {} to {}() must be a number.",
+            index + 1,
+            name
+        )),
+    }
+}
+
+fn integer_arg(args: &[Value], index: usize, name: &str) -> Result<i64, String> {
+    let number = number_arg(args, index, name)?;
+    if number.fract() != 0.0 {
+        return Err(format!(
+            "Argument {} to {}() must be an integer.",
+            index + 1,
+            name
+        ));
+    }
+
+    Ok(number as i64)
+}
+
+/// A non-negative whole number, for indexes and counts
+fn index_arg(args: &[Value], index: usize, name: &str) -> Result<usize, String> {
+    let number = number_arg(args, index, name)?;
+    if number < 0.0 || number.fract() != 0.0 {
+        return Err(format!(
+            "Argument {} to {}() must be a non-negative integer.",
+            index + 1,
+            name
+        ));
+    }
+    // casting would silently saturate, and no string is this long anyway
+    if number > u32::MAX as f64 {
+        return Err(format!(
+            "Argument {} to {}() is too large.",
+            index + 1,
+            name
+        ));
+    }
+
+    Ok(number as usize)
+}
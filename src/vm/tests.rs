@@ -0,0 +1,1091 @@
+use super::*;
+
+// Hand assembles chunks so the vm is tested without going through the compiler
+struct Assembler {
+    function: Function,
+}
+
+impl Assembler {
+    fn new(name: Option<&str>, arity: usize) -> Self {
+        let mut function = Function::new(name.map(Rc::from));
+        function.arity = arity;
+        Self { function }
+    }
+
+    fn op(mut self, op: OpCode) -> Self {
+        self.function.chunk.write_op(op, 1);
+        self
+    }
+
+    fn op_byte(mut self, op: OpCode, byte: u8) -> Self {
+        self.function.chunk.write_op(op, 1);
+        self.function.chunk.write(byte, 1);
+        self
+    }
+
+    fn op_constant(mut self, op: OpCode, value: Value) -> Self {
+        let constant = self.function.chunk.add_constant(value) as u8;
+        self.op_byte(op, constant)
+    }
+
+    fn op_invoke(self, op: OpCode, name: &str, arg_count: u8) -> Self {
+        let mut assembler = self.op_constant(op, Assembler::string(name));
+        assembler.function.chunk.write(arg_count, 1);
+        assembler
+    }
+
+    fn op_short(mut self, op: OpCode, short: u16) -> Self {
+        self.function.chunk.write_op(op, 1);
+        for byte in short.to_be_bytes().iter() {
+            self.function.chunk.write(*byte, 1);
+        }
+        self
+    }
+
+    fn constant(self, value: Value) -> Self {
+        self.op_constant(OpCode::Constant, value)
+    }
+
+    /// Wraps a function without upvalues in a closure
+    fn closure(self, function: Function) -> Self {
+        self.op_constant(OpCode::Closure, Value::Function(Rc::new(function)))
+    }
+
+    fn string(value: &str) -> Value {
+        Value::LoxString(Rc::from(value))
+    }
+
+    fn build(self) -> Function {
+        self.function
+    }
+}
+
+fn run(script: Function) -> (Result<(), InterpretError>, String) {
+    let mut vm = Vm::new(Vec::new());
+    let result = vm.run_script(script);
+    (result, String::from_utf8(vm.out).unwrap())
+}
+
+// Natives are easier to read exercised from source
+fn interpret(source: &str) -> (Result<(), InterpretError>, String) {
+    let mut vm = Vm::new(Vec::new());
+    let result = vm.interpret(String::from(source));
+    (result, String::from_utf8(vm.out).unwrap())
+}
+
+#[test]
+fn arithmetic_and_comparison() {
+    // print (1 + 2) * 3 / -2; print 1 < 2 == !nil;
+    let script = Assembler::new(None, 0)
+        .constant(Value::Number(1.0))
+        .constant(Value::Number(2.0))
+        .op(OpCode::Add)
+        .constant(Value::Number(3.0))
+        .op(OpCode::Multiply)
+        .constant(Value::Number(2.0))
+        .op(OpCode::Negate)
+        .op(OpCode::Divide)
+        .op(OpCode::Print)
+        .constant(Value::Number(1.0))
+        .constant(Value::Number(2.0))
+        .op(OpCode::Less)
+        .op(OpCode::Nil)
+        .op(OpCode::Not)
+        .op(OpCode::Equal)
+        .op(OpCode::Print)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, output) = run(script);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "-4.5\ntrue\n");
+}
+
+#[test]
+fn globals_and_string_concatenation() {
+    // var a = "foo"; a = a + "bar"; print a;
+    let script = Assembler::new(None, 0)
+        .constant(Assembler::string("foo"))
+        .op_constant(OpCode::DefineGlobal, Assembler::string("a"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("a"))
+        .constant(Assembler::string("bar"))
+        .op(OpCode::Add)
+        .op_constant(OpCode::SetGlobal, Assembler::string("a"))
+        .op(OpCode::Pop)
+        .op_constant(OpCode::GetGlobal, Assembler::string("a"))
+        .op(OpCode::Print)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, output) = run(script);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "foobar\n");
+}
+
+#[test]
+fn while_loop_over_local() {
+    // { var i = 0; while (i < 3) { print i; i = i + 1; } }
+    let script = Assembler::new(None, 0)
+        .constant(Value::Number(0.0))
+        // loop start, offset 2
+        .op_byte(OpCode::GetLocal, 1)
+        .constant(Value::Number(3.0))
+        .op(OpCode::Less)
+        .op_short(OpCode::JumpIfFalse, 15)
+        .op(OpCode::Pop)
+        .op_byte(OpCode::GetLocal, 1)
+        .op(OpCode::Print)
+        .op_byte(OpCode::GetLocal, 1)
+        .constant(Value::Number(1.0))
+        .op(OpCode::Add)
+        .op_byte(OpCode::SetLocal, 1)
+        .op(OpCode::Pop)
+        // back from offset 25, just past this instruction, to the loop start
+        .op_short(OpCode::Loop, 23)
+        .op(OpCode::Pop)
+        .op(OpCode::Pop)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, output) = run(script);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "0\n1\n2\n");
+}
+
+#[test]
+fn call_function_with_arguments() {
+    // fun add(a, b) { return a + b; } print add(1, 2);
+    let add = Assembler::new(Some("add"), 2)
+        .op_byte(OpCode::GetLocal, 1)
+        .op_byte(OpCode::GetLocal, 2)
+        .op(OpCode::Add)
+        .op(OpCode::Return)
+        .build();
+
+    let script = Assembler::new(None, 0)
+        .closure(add)
+        .op_constant(OpCode::DefineGlobal, Assembler::string("add"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("add"))
+        .constant(Value::Number(1.0))
+        .constant(Value::Number(2.0))
+        .op_byte(OpCode::Call, 2)
+        .op(OpCode::Print)
+        .op_constant(OpCode::GetGlobal, Assembler::string("add"))
+        .op(OpCode::Print)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, output) = run(script);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "3\n<fn add>\n");
+}
+
+#[test]
+fn call_native_clock() {
+    // print clock() > 0;
+    let script = Assembler::new(None, 0)
+        .op_constant(OpCode::GetGlobal, Assembler::string("clock"))
+        .op_byte(OpCode::Call, 0)
+        .constant(Value::Number(0.0))
+        .op(OpCode::Greater)
+        .op(OpCode::Print)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, output) = run(script);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "true\n");
+}
+
+#[test]
+fn class_with_initializer_and_method() {
+    // class Point {
+    //   init(x) { this.x = x; }
+    //   double() { return this.x * 2; }
+    // }
+    // var p = Point(21); print p.double(); print p; print p.x;
+    let init = Assembler::new(Some("init"), 1)
+        .op_byte(OpCode::GetLocal, 0)
+        .op_byte(OpCode::GetLocal, 1)
+        .op_constant(OpCode::SetProperty, Assembler::string("x"))
+        .op(OpCode::Pop)
+        .op_byte(OpCode::GetLocal, 0)
+        .op(OpCode::Return)
+        .build();
+
+    let double = Assembler::new(Some("double"), 0)
+        .op_byte(OpCode::GetLocal, 0)
+        .op_constant(OpCode::GetProperty, Assembler::string("x"))
+        .constant(Value::Number(2.0))
+        .op(OpCode::Multiply)
+        .op(OpCode::Return)
+        .build();
+
+    let script = Assembler::new(None, 0)
+        .op_constant(OpCode::Class, Assembler::string("Point"))
+        .op_constant(OpCode::DefineGlobal, Assembler::string("Point"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("Point"))
+        .closure(init)
+        .op_constant(OpCode::Method, Assembler::string("init"))
+        .closure(double)
+        .op_constant(OpCode::Method, Assembler::string("double"))
+        .op(OpCode::Pop)
+        .op_constant(OpCode::GetGlobal, Assembler::string("Point"))
+        .constant(Value::Number(21.0))
+        .op_byte(OpCode::Call, 1)
+        .op_constant(OpCode::DefineGlobal, Assembler::string("p"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("p"))
+        .op_invoke(OpCode::Invoke, "double", 0)
+        .op(OpCode::Print)
+        .op_constant(OpCode::GetGlobal, Assembler::string("p"))
+        .op(OpCode::Print)
+        .op_constant(OpCode::GetGlobal, Assembler::string("p"))
+        .op_constant(OpCode::GetProperty, Assembler::string("x"))
+        .op(OpCode::Print)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, output) = run(script);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "42\nPoint instance\n21\n");
+}
+
+#[test]
+fn inherited_method_is_bound_to_receiver() {
+    // class A { name() { return "A method"; } } class B < A {}
+    // var m = B().name; print m();
+    let name = Assembler::new(Some("name"), 0)
+        .constant(Assembler::string("A method"))
+        .op(OpCode::Return)
+        .build();
+
+    let script = Assembler::new(None, 0)
+        .op_constant(OpCode::Class, Assembler::string("A"))
+        .op_constant(OpCode::DefineGlobal, Assembler::string("A"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("A"))
+        .closure(name)
+        .op_constant(OpCode::Method, Assembler::string("name"))
+        .op(OpCode::Pop)
+        .op_constant(OpCode::Class, Assembler::string("B"))
+        .op_constant(OpCode::DefineGlobal, Assembler::string("B"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("A"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("B"))
+        .op(OpCode::Inherit)
+        .op(OpCode::Pop)
+        .op_constant(OpCode::GetGlobal, Assembler::string("B"))
+        .op_byte(OpCode::Call, 0)
+        .op_constant(OpCode::GetProperty, Assembler::string("name"))
+        .op_constant(OpCode::DefineGlobal, Assembler::string("m"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("m"))
+        .op(OpCode::Print)
+        .op_constant(OpCode::GetGlobal, Assembler::string("m"))
+        .op_byte(OpCode::Call, 0)
+        .op(OpCode::Print)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, output) = run(script);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "<fn name>\nA method\n");
+}
+
+#[test]
+fn super_access_skips_overriding_method() {
+    // class A { name() { return "A method"; } }
+    // class B < A {
+    //     name() { return "B method"; }
+    //     test() { print super.name(); var m = super.name; print m(); }
+    // }
+    // B().test();
+    // with the superclass read from a global until methods can capture the super local
+    let a_name = Assembler::new(Some("name"), 0)
+        .constant(Assembler::string("A method"))
+        .op(OpCode::Return)
+        .build();
+    let b_name = Assembler::new(Some("name"), 0)
+        .constant(Assembler::string("B method"))
+        .op(OpCode::Return)
+        .build();
+    let test = Assembler::new(Some("test"), 0)
+        .op_byte(OpCode::GetLocal, 0)
+        .op_constant(OpCode::GetGlobal, Assembler::string("A"))
+        .op_invoke(OpCode::SuperInvoke, "name", 0)
+        .op(OpCode::Print)
+        .op_byte(OpCode::GetLocal, 0)
+        .op_constant(OpCode::GetGlobal, Assembler::string("A"))
+        .op_constant(OpCode::GetSuper, Assembler::string("name"))
+        .op_byte(OpCode::Call, 0)
+        .op(OpCode::Print)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let script = Assembler::new(None, 0)
+        .op_constant(OpCode::Class, Assembler::string("A"))
+        .op_constant(OpCode::DefineGlobal, Assembler::string("A"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("A"))
+        .closure(a_name)
+        .op_constant(OpCode::Method, Assembler::string("name"))
+        .op(OpCode::Pop)
+        .op_constant(OpCode::Class, Assembler::string("B"))
+        .op_constant(OpCode::DefineGlobal, Assembler::string("B"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("A"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("B"))
+        .op(OpCode::Inherit)
+        .op_constant(OpCode::GetGlobal, Assembler::string("B"))
+        .closure(b_name)
+        .op_constant(OpCode::Method, Assembler::string("name"))
+        .closure(test)
+        .op_constant(OpCode::Method, Assembler::string("test"))
+        .op(OpCode::Pop)
+        .op_constant(OpCode::GetGlobal, Assembler::string("B"))
+        .op_byte(OpCode::Call, 0)
+        .op_invoke(OpCode::Invoke, "test", 0)
+        .op(OpCode::Pop)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, output) = run(script);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "A method\nA method\n");
+}
+
+#[test]
+fn runtime_error_on_bad_operand() {
+    // print "a"; print -"a";
+    let script = Assembler::new(None, 0)
+        .constant(Assembler::string("a"))
+        .op(OpCode::Print)
+        .constant(Assembler::string("a"))
+        .op(OpCode::Negate)
+        .op(OpCode::Print)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, output) = run(script);
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "Operand must be a number."
+        )))
+    );
+    assert_eq!(output, "a\n");
+}
+
+#[test]
+fn runtime_error_on_undefined_global() {
+    let script = Assembler::new(None, 0)
+        .op_constant(OpCode::GetGlobal, Assembler::string("missing"))
+        .op(OpCode::Print)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, _) = run(script);
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "Undefined variable 'missing'."
+        )))
+    );
+}
+
+#[test]
+fn runtime_error_on_arity_mismatch() {
+    let noop = Assembler::new(Some("noop"), 2)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let script = Assembler::new(None, 0)
+        .closure(noop)
+        .constant(Value::Number(1.0))
+        .op_byte(OpCode::Call, 1)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, _) = run(script);
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "Expected 2 arguments but got 1."
+        )))
+    );
+}
+
+#[test]
+fn runtime_error_on_stack_overflow() {
+    // fun f() { f(); } f();
+    let recurse = Assembler::new(Some("f"), 0)
+        .op_constant(OpCode::GetGlobal, Assembler::string("f"))
+        .op_byte(OpCode::Call, 0)
+        .op(OpCode::Pop)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let script = Assembler::new(None, 0)
+        .closure(recurse)
+        .op_constant(OpCode::DefineGlobal, Assembler::string("f"))
+        .op_constant(OpCode::GetGlobal, Assembler::string("f"))
+        .op_byte(OpCode::Call, 0)
+        .op(OpCode::Nil)
+        .op(OpCode::Return)
+        .build();
+
+    let (result, _) = run(script);
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "Stack overflow."
+        )))
+    );
+}
+
+#[test]
+fn deep_recursion_is_not_an_overflow() {
+    let (result, output) = interpret(
+        "fun f(n) { if (n == 0) return 0; return f(n - 1) + 1; }
+        print f(4000);",
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "4000\n");
+}
+
+#[test]
+fn string_natives() {
+    let (result, output) = interpret(
+        r#"print len("héllo");
+        print upper("abc") + lower("DEF");
+        print trim("  x  ");
+        print replace("a-b-c", "-", "+");
+        print find("héllo", "llo");
+        print find("abc", "z");
+        print starts_with("lox", "lo") and ends_with("lox", "ox");
+        print substr("wörld", 1, 3);
+        print char_at("✓ok", 0);
+        print repeat("ab", 3);
+        print parse_number(" 2.5 ") + 1;
+        print parse_number("nope");
+        print to_string(12) + to_string(nil);"#,
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        output,
+        "5\nABCdef\nx\na+b+c\n2\n-1\ntrue\nörl\n✓\nababab\n3.5\nnil\n12nil\n"
+    );
+}
+
+#[test]
+fn split_and_join() {
+    let (result, output) = interpret(
+        r#"var parts = split("a,b,,c", ",");
+        print len(parts);
+        print join(parts, "+");
+        print join(split("héj", ""), " ");
+        print split("", ",")[0] == "";
+        print join([], ", ") == "";
+        print "✓ok"[0] + "✓ok"[2];"#,
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "4\na+b++c\nh é j\ntrue\ntrue\n✓k\n");
+
+    let (result, _) = interpret(r#"join(["a", 1], ",");"#);
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "Argument 1 to join() must be a list of strings."
+        )))
+    );
+}
+
+#[test]
+fn string_native_argument_errors() {
+    let (result, _) = interpret("upper(1);");
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "Argument 1 to upper() must be a string."
+        )))
+    );
+
+    let (result, _) = interpret(r#"substr("abc", 2, 2);"#);
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "substr() range is out of bounds."
+        )))
+    );
+}
+
+#[test]
+fn string_natives_reject_huge_counts() {
+    let (result, _) = interpret(r#"substr("abc", 1, 99999999999999999999);"#);
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "Argument 3 to substr() is too large."
+        )))
+    );
+
+    let (result, _) = interpret(r#"substr("abc", 4000000000, 0);"#);
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "substr() range is out of bounds."
+        )))
+    );
+
+    let (result, _) = interpret(r#"repeat("a", 1000000000000);"#);
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "Argument 2 to repeat() is too large."
+        )))
+    );
+
+    let (result, _) = interpret(r#"repeat("ab", 4000000000);"#);
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "repeat() result is too long."
+        )))
+    );
+}
+
+#[test]
+fn math_natives() {
+    let (result, output) = interpret(
+        "print sqrt(16); print pow(2, 10); print floor(-1.5); print ceil(1.2);
+        print round(2.5); print abs(-3); print min(1, 2); print max(1, 2);
+        print sin(0); print cos(0); print atan2(1, 1) * 4 == pi; print e;",
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        output,
+        "4\n1024\n-2\n2\n3\n3\n1\n2\n0\n1\ntrue\n2.718281828459045\n"
+    );
+}
+
+#[test]
+fn random_is_deterministic() {
+    // exact values, scripts rely on printing the same numbers on every run and platform
+    let source = "print random(); print random_int(1, 6); print random_int(1, 6);
+        seed(42); print random_int(1, 100); print random();";
+    let expected = "0.8833108082136426\n1\n2\n14\n0.1599103928769201\n";
+
+    let (result, output) = interpret(source);
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, expected);
+
+    // a new vm starts over from the default seed
+    let (_, output) = interpret(source);
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn random_state_belongs_to_each_vm() {
+    let mut first = Vm::new(Vec::new());
+    first.interpret(String::from("print random();")).unwrap();
+    // creating another vm must not rewind the first one's sequence
+    let mut second = Vm::new(Vec::new());
+    second.interpret(String::from("print random();")).unwrap();
+    first.interpret(String::from("print random();")).unwrap();
+
+    let (_, one_number) = interpret("print random();");
+    let (_, two_numbers) = interpret("print random(); print random();");
+    assert_eq!(String::from_utf8(first.out).unwrap(), two_numbers);
+    assert_eq!(String::from_utf8(second.out).unwrap(), one_number);
+}
+
+#[test]
+fn random_int_rejects_bounds_past_exact_integers() {
+    let (result, _) = interpret("var big = 99999999999999999999; random_int(-big, big);");
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "random_int() bounds must be between -2^53 and 2^53."
+        )))
+    );
+
+    let (result, output) =
+        interpret("print random_int(-9007199254740992, 9007199254740992) != nil;");
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "true\n");
+}
+
+#[test]
+fn time_natives() {
+    // 2000-02-29T13:45:30Z, and one millisecond before the epoch
+    let (result, output) = interpret(
+        "var t = 951831930000;
+        print utc_year(t); print utc_month(t); print utc_day(t);
+        print utc_hour(t); print utc_minute(t); print utc_second(t);
+        print utc_year(-1); print utc_month(-1); print utc_second(-1);
+        var start = monotonic();
+        print sleep(1);
+        print monotonic() >= start + 1;
+        print now() > t;",
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        output,
+        "2000\n2\n29\n13\n45\n30\n1969\n12\n59\nnil\ntrue\ntrue\n"
+    );
+}
+
+#[test]
+fn sleep_rejects_durations_it_cannot_wait() {
+    for (source, message) in [
+        (
+            "sleep(-1);",
+            "sleep() duration must be a finite, non-negative number.",
+        ),
+        (
+            "sleep(0 / 0);",
+            "sleep() duration must be a finite, non-negative number.",
+        ),
+        (
+            "sleep(1 / 0);",
+            "sleep() duration must be a finite, non-negative number.",
+        ),
+        (
+            "var big = 99999999999999999999; sleep(big * big);",
+            "sleep() duration is too long.",
+        ),
+    ] {
+        let (result, _) = interpret(source);
+        assert_eq!(
+            result,
+            Err(InterpretError::RuntimeError(String::from(message)))
+        );
+    }
+}
+
+#[test]
+fn format_native() {
+    let (result, output) = interpret(
+        r#"print format("{:>8.2}|{:<5}|{:^7}|{}", 3.14159, "ab", "mid", nil);
+        print format("{:*^9}|{:6}|{:.3}|{{}}", "x", 42, "truncate");"#,
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        output,
+        "    3.14|ab   |  mid  |nil\n****x****|    42|tru|{}\n"
+    );
+
+    let (result, _) = interpret(r#"format("{} {}", 1);"#);
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "format() has more placeholders than arguments."
+        )))
+    );
+}
+
+#[test]
+fn reflection_natives() {
+    let (result, output) = interpret(
+        r#"class Shape { init(name) { this.name = name; } area() { return 0; } }
+        class Square < Shape {}
+        var square = Square("square");
+        print type(nil) + type(true) + type(1) + type("") + type(clock);
+        print type(Shape) + type(square) + type(square.area);
+        print has_field(square, "name") and !has_field(square, "side");
+        print has_field(1, "name");
+        print set_field(square, "side", 2) + get_field(square, "side");
+        print class_of(square);
+        print superclass(Square);
+        print superclass(Shape);
+        print arity(Square) + arity(square.area) + arity(clock);
+        print arity(format);
+        print fields(square);
+        print methods(Square);"#,
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        output,
+        "nilboolnumberstringfunction\nclassinstancefunction\ntrue\nfalse\n4\nSquare\nShape\nnil\n1\nnil\n\
+         [\"name\", \"side\"]\n[\"area\", \"init\"]\n"
+    );
+
+    let errors = [
+        (
+            r#"class A {} get_field(A, "name");"#,
+            "Only instances have fields.",
+        ),
+        (
+            "class A {} fields(A);",
+            "Argument 1 to fields() must be an instance.",
+        ),
+        (
+            "class A {} methods(A());",
+            "Argument 1 to methods() must be a class.",
+        ),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            interpret(source).0,
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        )
+    }
+}
+
+// Serves modules from memory, paths are matched exactly as the vm resolves them
+struct MemoryLoader {
+    files: HashMap<PathBuf, String>,
+}
+
+impl MemoryLoader {
+    fn new(files: &[(&str, &str)]) -> Self {
+        let files = files
+            .iter()
+            .map(|(path, source)| (PathBuf::from(path), String::from(*source)))
+            .collect();
+        Self { files }
+    }
+}
+
+impl ModuleLoader for MemoryLoader {
+    fn load(&self, path: &Path) -> std::io::Result<String> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| std::io::Error::from(ErrorKind::NotFound))
+    }
+}
+
+fn interpret_with_modules(
+    files: &[(&str, &str)],
+    search_paths: &[&str],
+    source: &str,
+) -> (Result<(), InterpretError>, String) {
+    let mut vm = Vm::new(Vec::new());
+    vm.set_module_loader(MemoryLoader::new(files));
+    vm.set_script_path(Path::new("app/main.lox"));
+    for path in search_paths {
+        vm.add_search_path(PathBuf::from(path));
+    }
+    let result = vm.interpret(String::from(source));
+    (result, String::from_utf8(vm.out).unwrap())
+}
+
+#[test]
+fn imports_run_each_module_once() {
+    let files = [
+        (
+            "app/util/math.lox",
+            "print \"loading math\"; var offset = 10; \
+             fun add(n) { return n + offset; } fun shift() { offset = offset + 1; }",
+        ),
+        (
+            "app/util/both.lox",
+            "import \"./math.lox\" as m; print m.add(0);",
+        ),
+    ];
+    let (result, output) = interpret_with_modules(
+        &files,
+        &[],
+        "import \"util/math.lox\" as math; from \"util/math.lox\" import add, offset; \
+         import \"util/both.lox\" as both; \
+         var offset = 0; math.shift(); \
+         print math.add(1); print add(1); print offset; print math.offset; print math;",
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        output,
+        "loading math\n10\n12\n12\n0\n11\n<module app/util/math.lox>\n"
+    );
+}
+
+#[test]
+fn modules_have_their_own_globals() {
+    let files = [(
+        "app/lib.lox",
+        "fun read() { return secret; } var n = len(\"abc\");",
+    )];
+    let (result, _) = interpret_with_modules(
+        &files,
+        &[],
+        "var secret = 1; import \"lib.lox\" as lib; print lib.n; lib.read();",
+    );
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "Undefined variable 'secret'."
+        )))
+    );
+}
+
+#[test]
+fn imports_fall_back_to_search_paths() {
+    let files = [
+        ("vendor/json.lox", "var name = \"vendored\";"),
+        ("std/json.lox", "var name = \"std\";"),
+        ("std/list.lox", "var name = \"list\";"),
+    ];
+    let (result, output) = interpret_with_modules(
+        &files,
+        &["vendor", "std"],
+        "from \"json.lox\" import name; print name; \
+         import \"list.lox\" as list; print list.name;",
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "vendored\nlist\n");
+}
+
+#[test]
+fn import_errors() {
+    let files = [
+        ("app/a.lox", "import \"b.lox\" as b;"),
+        ("app/b.lox", "import \"a.lox\" as a;"),
+        ("app/broken.lox", "var = 1;"),
+        ("app/empty.lox", ""),
+        ("app/throws.lox", "print \"running\"; throw \"failed\";"),
+    ];
+    let errors = [
+        (
+            "import \"a.lox\" as a;",
+            "Import cycle: app/a.lox -> app/b.lox -> app/a.lox.",
+        ),
+        (
+            "import \"missing.lox\" as m;",
+            "Could not find module 'missing.lox'.",
+        ),
+        (
+            "import \"broken.lox\" as m;",
+            "Could not compile module 'broken.lox'.",
+        ),
+        (
+            "import \"empty.lox\" as m; m.nope;",
+            "Module 'app/empty.lox' has no variable 'nope'.",
+        ),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            interpret_with_modules(&files, &[], source).0,
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        );
+    }
+
+    // a module that throws isn't cached, importing it again runs it again
+    let (result, output) = interpret_with_modules(
+        &files,
+        &[],
+        "for (var i = 0; i < 2; i = i + 1) { \
+           try { import \"throws.lox\" as t; } catch (e) { print e; } }",
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "running\nfailed\nrunning\nfailed\n");
+}
+
+#[test]
+fn file_natives() {
+    let dir = std::env::temp_dir().join(format!("rlox-file-natives-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("out.txt");
+    let source = format!(
+        r#"var path = "{path}";
+        print file_exists(path);
+        write_file(path, "one");
+        append_file(path, "-two");
+        print read_file(path);
+        print file_exists(path);
+        print list_dir("{dir}");"#,
+        path = path.display(),
+        dir = dir.display()
+    );
+    let (result, output) = interpret(&source);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(result, Ok(()));
+    assert_eq!(output, "false\none-two\ntrue\n[\"out.txt\"]\n");
+
+    let (result, _) = interpret(r#"read_file("/no/such/file");"#);
+    match result {
+        Err(InterpretError::RuntimeError(message)) => {
+            assert!(message.starts_with("Could not read '/no/such/file': "))
+        }
+        result => panic!("expected a runtime error, got {:?}", result),
+    }
+}
+
+#[test]
+fn process_natives() {
+    std::env::set_var("RLOX_TEST_ENV", "set");
+    let mut vm = Vm::new(Vec::new());
+    vm.set_args(vec![String::from("a"), String::from("b c")]);
+    let result = vm.interpret(String::from(
+        r#"print args();
+        print env("RLOX_TEST_ENV");
+        print env("RLOX_TEST_UNSET");
+        try { exit(3); } catch (e) { print "caught"; }
+        print "unreachable";"#,
+    ));
+    assert_eq!(result, Err(InterpretError::Exit(3)));
+    assert_eq!(
+        String::from_utf8(vm.out).unwrap(),
+        "[\"a\", \"b c\"]\nset\nnil\n"
+    );
+}
+
+#[test]
+fn sandbox_disables_io() {
+    let mut vm = Vm::new(Vec::new());
+    vm.disable_io();
+    vm.set_args(vec![String::from("x")]);
+    let result = vm.interpret(String::from(
+        r#"print args();
+        try { read_file("x"); } catch (e) { print e.message; }
+        exit(1);"#,
+    ));
+    assert_eq!(
+        result,
+        Err(InterpretError::RuntimeError(String::from(
+            "exit() is not available in a sandbox."
+        )))
+    );
+    assert_eq!(
+        String::from_utf8(vm.out).unwrap(),
+        "[\"x\"]\nread_file() is not available in a sandbox.\n"
+    );
+}
+
+#[test]
+fn json_parse_and_stringify() {
+    let mut vm = Vm::new(Vec::new());
+    // lox strings have no escapes, so the document comes in as a global
+    let document = r#"{"a": [1, 2.5, -3e2, true, null], "b": {"c": "x\né😀 \"q\""}, "d": {}}"#;
+    vm.builtins
+        .insert(Rc::from("document"), Value::LoxString(Rc::from(document)));
+    let result = vm.interpret(String::from(
+        r#"var data = json.parse(document);
+        print data["a"];
+        print json.stringify(data);
+        print json.stringify([1, {"k": []}], 2);
+        print json.stringify(json.parse(json.stringify(data))) == json.stringify(data);"#,
+    ));
+    assert_eq!(result, Ok(()));
+    assert_eq!(
+        String::from_utf8(vm.out).unwrap(),
+        "[1, 2.5, -300, true, nil]\n\
+         {\"a\":[1,2.5,-300,true,null],\"b\":{\"c\":\"x\\né😀 \\\"q\\\"\"},\"d\":{}}\n\
+         [\n  1,\n  {\n    \"k\": []\n  }\n]\n\
+         true\n"
+    );
+}
+
+#[test]
+fn json_errors() {
+    let errors = [
+        (
+            "var l = [1]; l.push(l); json.stringify(l);",
+            "Can't convert a value that contains itself to JSON.",
+        ),
+        (
+            r#"json.stringify({"f": clock});"#,
+            "Can't convert <native fn> to JSON.",
+        ),
+        (
+            "json.stringify({1: 2});",
+            "Can't convert map key 1 to JSON, keys must be strings.",
+        ),
+        (
+            "json.stringify(1, -1);",
+            "Argument 2 to stringify() must be a non-negative integer.",
+        ),
+        (
+            r#"json.parse("[1, 2");"#,
+            "Invalid JSON at character 5: Expect ',' or ']' after value.",
+        ),
+        (
+            r#"json.parse("01");"#,
+            "Invalid JSON at character 1: Expect end of input.",
+        ),
+        (
+            r#"json.parse("{1: 2}");"#,
+            "Invalid JSON at character 1: Expect a string key.",
+        ),
+        (
+            r#"json.parse(repeat("[", 1000));"#,
+            "JSON is nested too deeply.",
+        ),
+    ];
+    for (source, message) in errors.iter() {
+        assert_eq!(
+            interpret(source).0,
+            Err(InterpretError::RuntimeError(String::from(*message))),
+            "{}",
+            source
+        )
+    }
+}
+
+#[test]
+fn runs_test_blocks() {
+    let mut vm = Vm::new(Vec::new());
+    vm.enable_tests();
+    let result = vm.interpret(String::from(
+        r#"var count = 0;
+        test "first" { count = count + 1; print "first"; }
+        test "second fails" { assert count == 0, "count is " + to_string(count); }
+        test "third" { print "third"; }
+        print "top level";"#,
+    ));
+    assert_eq!(result, Ok(()));
+
+    let results: Vec<(String, Result<(), InterpretError>)> = vm
+        .run_tests("")
+        .into_iter()
+        .map(|(name, result)| (name.to_string(), result))
+        .collect();
+    assert_eq!(
+        results,
+        vec![
+            (String::from("first"), Ok(())),
+            (
+                String::from("second fails"),
+                Err(InterpretError::RuntimeError(String::from(
+                    "Assertion failed: count == 0 (line 3, columns 38-47): count is 1"
+                )))
+            ),
+            (String::from("third"), Ok(())),
+        ]
+    );
+    // the tests are only collected once
+    assert!(vm.run_tests("").is_empty());
+    assert_eq!(
+        String::from_utf8(vm.out).unwrap(),
+        "top level\nfirst\nthird\n"
+    );
+}
+
+#[test]
+fn filters_test_blocks_by_name() {
+    let mut vm = Vm::new(Vec::new());
+    vm.enable_tests();
+    let result = vm.interpret(String::from(
+        r#"test "parses numbers" { print 1; } test "prints" { print 2; }"#,
+    ));
+    assert_eq!(result, Ok(()));
+    let names: Vec<Rc<str>> = vm
+        .run_tests("parse")
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    assert_eq!(names, vec![Rc::from("parses numbers")]);
+    assert_eq!(String::from_utf8(vm.out).unwrap(), "1\n");
+}